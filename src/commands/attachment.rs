@@ -0,0 +1,118 @@
+use std::fs;
+use std::io::{self, Cursor, ErrorKind, Write};
+use std::path::Path;
+
+use lettre::message::header::ContentType;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::commands::units::human_size;
+use crate::data_struct::FileMetadata;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManifestFormat {
+    Csv,
+    Json,
+}
+
+/// A file to attach to an outgoing email.
+pub struct EmailAttachment {
+    pub file_name: String,
+    pub content_type: ContentType,
+    pub data: Vec<u8>,
+}
+
+fn content_type(value: &str) -> ContentType {
+    ContentType::parse(value).unwrap_or(ContentType::TEXT_PLAIN)
+}
+
+pub fn file_attachment(path: &Path) -> io::Result<EmailAttachment> {
+    let file_name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "attachment".to_string());
+
+    Ok(EmailAttachment {
+        file_name,
+        content_type: content_type("application/octet-stream"),
+        data: fs::read(path)?,
+    })
+}
+
+/// Bundle several attachments into a single deflated zip archive.
+pub fn zip_attachments(archive_name: &str, attachments: Vec<EmailAttachment>) -> io::Result<EmailAttachment> {
+    let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+
+    for attachment in attachments {
+        writer
+            .start_file(attachment.file_name.as_str(), options)
+            .map_err(|e| io::Error::other(format!("Could not zip {}: {}", attachment.file_name, e)))?;
+        writer.write_all(&attachment.data)?;
+    }
+
+    let archive = writer
+        .finish()
+        .map_err(|e| io::Error::other(format!("Could not finish zip archive: {e}")))?;
+
+    Ok(EmailAttachment {
+        file_name: archive_name.to_string(),
+        content_type: content_type("application/zip"),
+        data: archive.into_inner(),
+    })
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// A listing of an indexed folder's `files.json`, as CSV or JSON.
+pub fn manifest_attachment(files: &[FileMetadata], format: ManifestFormat) -> io::Result<EmailAttachment> {
+    match format {
+        ManifestFormat::Json => Ok(EmailAttachment {
+            file_name: "manifest.json".to_string(),
+            content_type: content_type("application/json"),
+            data: serde_json::to_vec_pretty(files)?,
+        }),
+        ManifestFormat::Csv => {
+            let mut csv = String::from("key,path,file_type,size,hash,ctime,mtime\n");
+            for file in files {
+                let row = [
+                    csv_field(&file.key),
+                    csv_field(&file.path),
+                    csv_field(&file.file_type),
+                    file.size.to_string(),
+                    csv_field(file.hash.as_deref().unwrap_or_default()),
+                    file.ctime.to_string(),
+                    file.mtime.to_string(),
+                ];
+                csv.push_str(&row.join(","));
+                csv.push('\n');
+            }
+            Ok(EmailAttachment {
+                file_name: "manifest.csv".to_string(),
+                content_type: content_type("text/csv"),
+                data: csv.into_bytes(),
+            })
+        }
+    }
+}
+
+/// Refuse to send when attachments adding up to `total` bytes exceed `max_size`.
+pub fn check_attachment_size(total: u64, max_size: u64) -> io::Result<()> {
+    if total > max_size {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Attachments total {} which exceeds the {} limit (see --max-attachment-size)",
+                human_size(total),
+                human_size(max_size)
+            ),
+        ));
+    }
+    Ok(())
+}
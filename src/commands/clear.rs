@@ -1,11 +1,15 @@
+use std::collections::BTreeSet;
 use std::fs;
 use std::io;
 use std::path::PathBuf;
+use std::time::Duration;
 use serde_json;
 use crate::commands::global::get_global_bof_dir;
+use crate::commands::lock::lock_dir;
 
-pub fn clear_all_bof_dirs() -> io::Result<()> {
+pub fn clear_all_bof_dirs(lock_wait: Option<Duration>) -> io::Result<()> {
     let global_bof_dir = get_global_bof_dir()?;
+    let global_lock = lock_dir(&global_bof_dir, lock_wait)?;
     let inverse_table_path = global_bof_dir.join("inverse_table.json");
 
     if !inverse_table_path.exists() {
@@ -18,22 +22,30 @@ pub fn clear_all_bof_dirs() -> io::Result<()> {
     let data = fs::read_to_string(&inverse_table_path)?;
     let inverse_table: serde_json::Value = serde_json::from_str(&data)?;
 
+    let mut roots = BTreeSet::new();
     if let Some(files) = inverse_table.get("files") {
         for (_, file_entry) in files.as_object().unwrap() {
             if let Some(directories) = file_entry.get("directories") {
                 for dir in directories.as_array().unwrap() {
-                    let dir_path = dir.as_str().unwrap();
-                    let bof_dir = PathBuf::from(dir_path).join(".bof");
-
-                    if bof_dir.exists() {
-                        fs::remove_dir_all(&bof_dir)?;
-                        println!("Removed: {:?}", bof_dir);
-                    }
+                    roots.insert(PathBuf::from(dir.as_str().unwrap()));
                 }
             }
         }
     }
+    // `bof index` takes a folder's lock before the global one; release the global
+    // lock first so the two can't wait on each other.
+    drop(global_lock);
+
+    for root in roots {
+        let bof_dir = root.join(".bof");
+        if bof_dir.exists() {
+            // Wait for any index run on this folder to finish before removing it.
+            let _local_lock = lock_dir(&bof_dir, lock_wait)?;
+            fs::remove_dir_all(&bof_dir)?;
+            println!("Removed: {:?}", bof_dir);
+        }
+    }
 
     println!("All .bof directories removed.");
     Ok(())
-}
\ No newline at end of file
+}
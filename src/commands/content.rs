@@ -0,0 +1,226 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File};
+use std::io::{self, ErrorKind, Read};
+use std::path::{Path, PathBuf};
+
+use crate::commands::global::write_atomic;
+use crate::commands::index::indexed_roots;
+use crate::data_struct::{ContentIndex, FileMetadata};
+
+const CONTENT_FILE: &str = "content.json";
+/// Default `--content-max-size`: bigger files are rarely worth grepping.
+pub const DEFAULT_MAX_CONTENT_SIZE: u64 = 1 << 20;
+const MAX_TERM_LEN: usize = 64;
+const MAX_SNIPPETS: usize = 3;
+const MAX_SNIPPET_LEN: usize = 160;
+/// Bytes read to decide whether a file without a known extension is text.
+const SNIFF_LEN: usize = 1024;
+
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "md", "markdown", "rst", "adoc", "org", "tex", "log", "csv", "tsv",
+    "json", "toml", "yaml", "yml", "ini", "cfg", "conf", "xml", "html", "htm", "css", "svg",
+    "rs", "py", "js", "mjs", "ts", "tsx", "jsx", "c", "h", "cc", "cpp", "hpp", "java", "kt", "go",
+    "rb", "php", "pl", "lua", "swift", "scala", "cs", "sh", "bash", "zsh", "fish", "ps1", "bat",
+    "sql", "r", "m", "vue", "gradle", "properties", "env", "gitignore", "dockerfile", "makefile",
+];
+
+/// Words of `text`: lowercased runs of letters, digits and `_`, two characters or longer.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|word| word.chars().nth(1).is_some() && word.len() <= MAX_TERM_LEN)
+        .map(|word| word.to_lowercase())
+}
+
+/// Text-like by extension, or for files without a known one, when the first
+/// bytes hold no NUL and decode as UTF-8.
+fn is_text_like(path: &Path) -> io::Result<bool> {
+    let known = path
+        .extension()
+        .or_else(|| path.file_name())
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .is_some_and(|e| TEXT_EXTENSIONS.contains(&e.trim_start_matches('.')));
+    if known {
+        return Ok(true);
+    }
+    if path.extension().is_some() {
+        return Ok(false);
+    }
+
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    File::open(path)?.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    if head.contains(&0) {
+        return Ok(false);
+    }
+    match std::str::from_utf8(&head) {
+        Ok(_) => Ok(true),
+        // A multi-byte character cut off at the end of the sample is fine.
+        Err(e) => Ok(e.error_len().is_none()),
+    }
+}
+
+/// The file's contents, or `None` when they hold a NUL or are not UTF-8.
+fn read_text(path: &Path) -> io::Result<Option<String>> {
+    let data = fs::read(path)?;
+    if data.contains(&0) {
+        return Ok(None);
+    }
+    Ok(String::from_utf8(data).ok())
+}
+
+pub fn load_content_index(bof_dir: &Path) -> io::Result<Option<ContentIndex>> {
+    let path = bof_dir.join(CONTENT_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let data = fs::read_to_string(&path)?;
+    serde_json::from_str(&data).map(Some).map_err(|e| {
+        io::Error::new(ErrorKind::InvalidData, format!("Could not parse {}: {}", path.display(), e))
+    })
+}
+
+/// Bring `.bof/content.json` in line with `files`: drop entries for files that
+/// changed or vanished and index the words of new or changed text files up to
+/// `max_size` bytes. Files found not to be text are remembered by hash and
+/// only looked at again once they change. With `max_size` unset, only an
+/// existing index is updated, using the size cap it was built with. Returns
+/// the number of files (re)read.
+pub fn update_content_index(
+    root: &Path,
+    bof_dir: &Path,
+    files: &[FileMetadata],
+    max_size: Option<u64>,
+) -> io::Result<Option<usize>> {
+    let mut index = match (load_content_index(bof_dir)?, max_size) {
+        (Some(mut index), Some(max_size)) => {
+            index.max_size = max_size;
+            index
+        }
+        (Some(index), None) => index,
+        (None, Some(max_size)) => ContentIndex { max_size, ..Default::default() },
+        (None, None) => return Ok(None),
+    };
+
+    let wanted: HashMap<&str, &str> = files
+        .iter()
+        .filter(|f| f.file_type == "file" && f.size <= index.max_size)
+        .filter_map(|f| Some((f.path.as_str(), f.hash.as_deref()?)))
+        .collect();
+
+    let stale: BTreeSet<String> = index
+        .documents
+        .iter()
+        .filter(|(path, hash)| wanted.get(path.as_str()) != Some(&hash.as_str()))
+        .map(|(path, _)| path.clone())
+        .collect();
+    if !stale.is_empty() {
+        for paths in index.terms.values_mut() {
+            paths.retain(|p| !stale.contains(p));
+        }
+        index.terms.retain(|_, paths| !paths.is_empty());
+        index.documents.retain(|path, _| !stale.contains(path));
+    }
+    index.skipped.retain(|path, hash| wanted.get(path.as_str()) == Some(&hash.as_str()));
+
+    let mut read = 0;
+    for (path, hash) in wanted {
+        if index.documents.contains_key(path) || index.skipped.contains_key(path) {
+            continue;
+        }
+        // Unreadable files are retried next run; only a verdict on the
+        // contents is remembered.
+        let full_path = root.join(path.trim_start_matches("./"));
+        let text = match is_text_like(&full_path) {
+            Ok(true) => read_text(&full_path),
+            Ok(false) => Ok(None),
+            Err(e) => Err(e),
+        };
+        let text = match text {
+            Ok(Some(text)) => text,
+            Ok(None) => {
+                index.skipped.insert(path.to_string(), hash.to_string());
+                continue;
+            }
+            Err(_) => continue,
+        };
+
+        for term in tokenize(&text) {
+            index.terms.entry(term).or_default().insert(path.to_string());
+        }
+        index.documents.insert(path.to_string(), hash.to_string());
+        read += 1;
+    }
+
+    write_atomic(&bof_dir.join(CONTENT_FILE), serde_json::to_string(&index)?.as_bytes())?;
+    Ok(Some(read))
+}
+
+/// A file containing every searched term, with lines showing them.
+pub struct GrepMatch {
+    pub location: PathBuf,
+    /// 1-based line number and the (shortened) line.
+    pub snippets: Vec<(usize, String)>,
+}
+
+fn shorten(line: &str) -> String {
+    let line = line.trim();
+    if line.chars().count() <= MAX_SNIPPET_LEN {
+        return line.to_string();
+    }
+    let cut: String = line.chars().take(MAX_SNIPPET_LEN).collect();
+    format!("{}...", cut)
+}
+
+/// Find the indexed text files, across every registered root, that contain all
+/// of the words in `query`. Files whose current contents no longer match are
+/// left out, so a slightly stale index never shows wrong snippets.
+pub fn grep(query: &[String]) -> io::Result<Vec<GrepMatch>> {
+    let terms: BTreeSet<String> = query.iter().flat_map(|q| tokenize(q).collect::<Vec<_>>()).collect();
+    if terms.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidInput,
+            "Search terms must contain a word of at least two letters or digits",
+        ));
+    }
+
+    let mut matches = Vec::new();
+    for root in indexed_roots()? {
+        let Some(index) = load_content_index(&root.join(".bof"))? else {
+            continue;
+        };
+
+        let mut candidates: Option<BTreeSet<&String>> = None;
+        for term in &terms {
+            let paths: BTreeSet<&String> = index.terms.get(term).map(|p| p.iter().collect()).unwrap_or_default();
+            candidates = Some(match candidates {
+                Some(found) => found.intersection(&paths).copied().collect(),
+                None => paths,
+            });
+        }
+
+        for path in candidates.unwrap_or_default() {
+            let location = root.join(path.trim_start_matches("./"));
+            let Ok(Some(text)) = read_text(&location) else {
+                continue;
+            };
+
+            let mut found: BTreeSet<String> = BTreeSet::new();
+            let mut snippets = Vec::new();
+            for (number, line) in text.lines().enumerate() {
+                let line_terms: Vec<String> = tokenize(line).filter(|t| terms.contains(t)).collect();
+                if line_terms.is_empty() {
+                    continue;
+                }
+                found.extend(line_terms);
+                if snippets.len() < MAX_SNIPPETS {
+                    snippets.push((number + 1, shorten(line)));
+                }
+            }
+
+            if found.len() == terms.len() {
+                matches.push(GrepMatch { location, snippets });
+            }
+        }
+    }
+
+    Ok(matches)
+}
@@ -0,0 +1,248 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::commands::email_config::load_email_config;
+use crate::commands::global::{get_global_bof_dir, write_atomic};
+use crate::commands::index::{indexed_roots, load_metadata, location};
+use crate::commands::outbox::queued_id;
+use crate::commands::send_email::{send_email, Recipients};
+use crate::commands::template::{local_time, RenderedEmail};
+use crate::commands::units::human_size;
+use crate::data_struct::{DigestEntry, DigestState};
+
+/// Longest list printed per section; the rest is summarized as a count.
+const MAX_LISTED: usize = 50;
+
+/// A file that appeared, changed or disappeared since the last digest.
+#[derive(Debug)]
+pub struct DigestChange {
+    pub location: String,
+    pub size: u64,
+}
+
+/// Files sharing a hash that gained a copy since the last digest.
+#[derive(Debug)]
+pub struct DuplicateGroup {
+    pub hash: String,
+    pub locations: Vec<String>,
+}
+
+/// What changed across the indexed roots since the last digest.
+#[derive(Debug, Default)]
+pub struct Digest {
+    pub since: u64,
+    pub roots: usize,
+    pub new: Vec<DigestChange>,
+    pub modified: Vec<DigestChange>,
+    pub deleted: Vec<DigestChange>,
+    pub size_delta: i64,
+    pub new_duplicates: Vec<DuplicateGroup>,
+}
+
+impl Digest {
+    pub fn is_empty(&self) -> bool {
+        self.new.is_empty() && self.modified.is_empty() && self.deleted.is_empty() && self.new_duplicates.is_empty()
+    }
+}
+
+fn digest_state_path() -> io::Result<PathBuf> {
+    Ok(get_global_bof_dir()?.join("digest_state.json"))
+}
+
+fn load_state() -> io::Result<DigestState> {
+    let path = digest_state_path()?;
+    if !path.exists() {
+        return Ok(DigestState::default());
+    }
+    let data = fs::read_to_string(&path)?;
+    serde_json::from_str(&data).map_err(|e| {
+        io::Error::new(ErrorKind::InvalidData, format!("Could not parse {}: {}", path.display(), e))
+    })
+}
+
+/// Snapshot every indexed root's `files.json`. Roots from the previous state
+/// are included too, so a root that was cleared shows its files as deleted.
+fn current_state(previous: &DigestState) -> io::Result<DigestState> {
+    let mut roots: BTreeSet<PathBuf> = indexed_roots()?;
+    roots.extend(previous.roots.keys().map(PathBuf::from));
+
+    let mut state = DigestState {
+        last_digest: SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0),
+        roots: BTreeMap::new(),
+    };
+
+    for root in roots {
+        let (files, _) = load_metadata(&root.join(".bof"))?;
+        if files.is_empty() {
+            continue;
+        }
+        let entries = files
+            .into_iter()
+            .map(|f| (f.path, DigestEntry { hash: f.hash, size: f.size }))
+            .collect();
+        state.roots.insert(root.to_string_lossy().to_string(), entries);
+    }
+    Ok(state)
+}
+
+fn hash_locations(state: &DigestState) -> BTreeMap<&str, Vec<String>> {
+    let mut by_hash: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    for (root, entries) in &state.roots {
+        for (path, entry) in entries {
+            if let Some(hash) = &entry.hash {
+                by_hash.entry(hash.as_str()).or_default().push(location(Path::new(root), path));
+            }
+        }
+    }
+    by_hash
+}
+
+fn compare(previous: &DigestState, current: &DigestState) -> Digest {
+    let empty = BTreeMap::new();
+    let mut digest = Digest {
+        since: previous.last_digest,
+        roots: current.roots.len(),
+        ..Default::default()
+    };
+
+    let all_roots: BTreeSet<&String> = previous.roots.keys().chain(current.roots.keys()).collect();
+    for root in all_roots {
+        let before = previous.roots.get(root).unwrap_or(&empty);
+        let after = current.roots.get(root).unwrap_or(&empty);
+
+        for (path, entry) in after {
+            let change = DigestChange { location: location(Path::new(root), path), size: entry.size };
+            match before.get(path) {
+                None => digest.new.push(change),
+                Some(old) if old != entry => digest.modified.push(change),
+                Some(_) => {}
+            }
+        }
+        for (path, entry) in before {
+            if !after.contains_key(path) {
+                digest.deleted.push(DigestChange { location: location(Path::new(root), path), size: entry.size });
+            }
+        }
+
+        let total = |entries: &BTreeMap<String, DigestEntry>| entries.values().map(|e| e.size as i64).sum::<i64>();
+        digest.size_delta += total(after) - total(before);
+    }
+
+    let before_counts = hash_locations(previous);
+    for (hash, locations) in hash_locations(current) {
+        let before = before_counts.get(hash).map_or(0, |l| l.len());
+        if locations.len() >= 2 && locations.len() > before {
+            digest.new_duplicates.push(DuplicateGroup { hash: hash.to_string(), locations });
+        }
+    }
+
+    digest
+}
+
+fn signed_size(delta: i64) -> String {
+    let sign = if delta < 0 { "-" } else { "+" };
+    format!("{}{}", sign, human_size(delta.unsigned_abs()))
+}
+
+fn push_section(out: &mut String, title: &str, changes: &[DigestChange]) {
+    if changes.is_empty() {
+        return;
+    }
+    out.push_str(&format!("\n{} ({}):\n", title, changes.len()));
+    for change in changes.iter().take(MAX_LISTED) {
+        out.push_str(&format!("  {} ({})\n", change.location, human_size(change.size)));
+    }
+    if changes.len() > MAX_LISTED {
+        out.push_str(&format!("  ... and {} more\n", changes.len() - MAX_LISTED));
+    }
+}
+
+/// A plain-text report of `digest`, used both on the terminal and as the email body.
+pub fn format_digest(digest: &Digest) -> String {
+    let mut out = if digest.since == 0 {
+        "Changes across indexed folders (first digest)\n".to_string()
+    } else {
+        format!("Changes across indexed folders since {}\n", local_time(digest.since))
+    };
+
+    out.push_str(&format!(
+        "\n{} new, {} modified, {} deleted in {} folder(s); total size change {}\n",
+        digest.new.len(),
+        digest.modified.len(),
+        digest.deleted.len(),
+        digest.roots,
+        signed_size(digest.size_delta)
+    ));
+
+    push_section(&mut out, "New files", &digest.new);
+    push_section(&mut out, "Modified files", &digest.modified);
+    push_section(&mut out, "Deleted files", &digest.deleted);
+
+    if !digest.new_duplicates.is_empty() {
+        out.push_str(&format!("\nNew duplicates ({}):\n", digest.new_duplicates.len()));
+        for group in digest.new_duplicates.iter().take(MAX_LISTED) {
+            out.push_str(&format!("  {}\n", group.hash));
+            for location in &group.locations {
+                out.push_str(&format!("    {}\n", location));
+            }
+        }
+        if digest.new_duplicates.len() > MAX_LISTED {
+            out.push_str(&format!("  ... and {} more\n", digest.new_duplicates.len() - MAX_LISTED));
+        }
+    }
+
+    out
+}
+
+/// Print what changed since the last digest, or with `recipients` email it and
+/// remember the current state as the starting point of the next digest. The
+/// state moves forward once the email was sent or saved to the outbox (which
+/// delivers it later, so the next digest must not repeat these changes); a
+/// preview or a failure to send leaves it alone.
+pub fn run_digest(recipients: Option<&Recipients>, profile: Option<&str>) -> io::Result<()> {
+    let previous = load_state()?;
+    let current = current_state(&previous)?;
+    let digest = compare(&previous, &current);
+    let report = format_digest(&digest);
+
+    let Some(recipients) = recipients else {
+        print!("{}", report);
+        return Ok(());
+    };
+
+    if digest.is_empty() {
+        println!("Nothing changed since the last digest; no email sent.");
+        return Ok(());
+    }
+
+    let email = RenderedEmail {
+        subject: format!(
+            "bof digest: {} new, {} modified, {} deleted",
+            digest.new.len(),
+            digest.modified.len(),
+            digest.deleted.len()
+        ),
+        text: report,
+        html: None,
+    };
+    let config = load_email_config(profile)?;
+    let sent = send_email(&config, profile, recipients, &email, &[]);
+    if let Err(e) = &sent {
+        if queued_id(e).is_none() {
+            return sent;
+        }
+    }
+
+    let json = serde_json::to_vec_pretty(&current)?;
+    write_atomic(&digest_state_path()?, &json)?;
+    if sent.is_ok() {
+        println!("Digest sent.");
+    }
+    sent
+}
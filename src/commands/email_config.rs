@@ -1,35 +1,275 @@
+use std::env;
 use std::fs;
-use std::io::{self, Write};
-use serde::{Serialize, Deserialize};
-use dirs::home_dir;
-use crate::data_struct::EmailConfig;
-use crate::commands::global::get_global_bof_dir;
+use std::io::{self, BufRead, ErrorKind, IsTerminal};
+use std::path::PathBuf;
+use std::process::Command;
+use crate::data_struct::{EmailConfig, EmailProfiles, SmtpSecurity};
+use crate::commands::global::{get_global_bof_dir, write_private};
+use crate::commands::secret::{decrypt_secret, encrypt_secret, read_passphrase};
 
+/// Profile name used when none is given and no default has been chosen yet.
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// Where `config-email` gets the SMTP password from.
+pub enum PasswordSource {
+    /// Read once from stdin (or a hidden prompt) and stored encrypted.
+    Stdin,
+    /// Looked up in this environment variable each time an email is sent.
+    Env(String),
+    /// Printed by this shell command each time an email is sent.
+    Command(String),
+}
+
+/// Parse a `--security` value; without one, port 465 implies implicit TLS and
+/// anything else STARTTLS.
+pub fn parse_security(security: Option<&str>, port: u16) -> io::Result<SmtpSecurity> {
+    match security {
+        Some("starttls") => Ok(SmtpSecurity::StartTls),
+        Some("tls") => Ok(SmtpSecurity::Tls),
+        Some("plain") => Ok(SmtpSecurity::Plain),
+        Some(other) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Unknown security mode '{}' (expected starttls, tls or plain)", other),
+        )),
+        None if port == 465 => Ok(SmtpSecurity::Tls),
+        None => Ok(SmtpSecurity::StartTls),
+    }
+}
+
+fn read_password_from_stdin() -> io::Result<String> {
+    let stdin = io::stdin();
+    let password = if stdin.is_terminal() {
+        rpassword::prompt_password("SMTP password: ")?
+    } else {
+        let mut line = String::new();
+        stdin.lock().read_line(&mut line)?;
+        line.trim_end_matches(['\r', '\n']).to_string()
+    };
+
+    if password.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "No password given on stdin"));
+    }
+    Ok(password)
+}
+
+fn run_password_command(command: &str) -> io::Result<String> {
+    #[cfg(windows)]
+    let output = Command::new("cmd").args(["/C", command]).output()?;
+    #[cfg(not(windows))]
+    let output = Command::new("sh").args(["-c", command]).output()?;
+
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "Password command '{}' failed with {}",
+            command, output.status
+        )));
+    }
+
+    let stdout = String::from_utf8(output.stdout)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Password command printed invalid UTF-8"))?;
+    Ok(stdout.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn email_config_path() -> io::Result<PathBuf> {
+    Ok(get_global_bof_dir()?.join("email_config.json"))
+}
+
+/// Load every stored profile. A config written before profiles existed holds a
+/// single `EmailConfig`, which is read as the default profile. Profiles still
+/// holding a plaintext password are reported.
+pub fn load_profiles() -> io::Result<EmailProfiles> {
+    let config_path = email_config_path()?;
+    if !config_path.exists() {
+        return Ok(EmailProfiles::default());
+    }
+
+    let data = fs::read_to_string(&config_path)?;
+    let value: serde_json::Value = serde_json::from_str(&data)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Could not parse config: {e}")))?;
+
+    let profiles = if value.get("address").is_some() {
+        let legacy: EmailConfig = serde_json::from_value(value)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Could not parse config: {e}")))?;
+        let mut profiles = EmailProfiles {
+            default: Some(DEFAULT_PROFILE.to_string()),
+            ..Default::default()
+        };
+        profiles.profiles.insert(DEFAULT_PROFILE.to_string(), legacy);
+        profiles
+    } else {
+        serde_json::from_value(value)
+            .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Could not parse config: {e}")))?
+    };
+
+    for name in plaintext_profiles(&profiles) {
+        eprintln!(
+            "Warning: email profile '{}' stores its password in plaintext in {}; re-run 'bof config-email --profile {}' to replace it.",
+            name,
+            config_path.display(),
+            name
+        );
+    }
+    Ok(profiles)
+}
+
+/// Names of the profiles holding a plaintext `password`, as configs written
+/// before passwords were encrypted do.
+fn plaintext_profiles(profiles: &EmailProfiles) -> Vec<&str> {
+    profiles
+        .profiles
+        .iter()
+        .filter(|(_, config)| !config.password.is_empty())
+        .map(|(name, _)| name.as_str())
+        .collect()
+}
+
+/// Write `profiles` back. A plaintext password is never written: saving fails
+/// until every such profile has been configured again.
+fn save_profiles(profiles: &EmailProfiles) -> io::Result<PathBuf> {
+    if let Some(name) = plaintext_profiles(profiles).first() {
+        return Err(io::Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Email profile '{}' stores its password in plaintext; re-run 'bof config-email --profile {}' first",
+                name, name
+            ),
+        ));
+    }
+
+    let config_path = email_config_path()?;
+    let json_data = serde_json::to_string_pretty(profiles)
+        .map_err(|e| io::Error::other(format!("Serialize error: {e}")))?;
+    write_private(&config_path, json_data.as_bytes())?;
+    Ok(config_path)
+}
 
 pub fn config_email_command(
+    profile: Option<&str>,
+    make_default: bool,
     address: &str,
-    password: &str,
+    password_source: PasswordSource,
     server: &str,
-    port_str: &str
+    port_str: &str,
+    security: Option<&str>,
 ) -> io::Result<()> {
     let port: u16 = port_str.parse().unwrap_or(587);
 
-    let email_config = EmailConfig {
+    let mut email_config = EmailConfig {
         address: address.to_string(),
-        password: password.to_string(),
+        password: String::new(),
+        encrypted_password: None,
+        password_env: None,
+        password_command: None,
         server: server.to_string(),
         port,
+        security: parse_security(security, port)?,
     };
 
-    let global_bof_dir = get_global_bof_dir()?;
-    let config_path = global_bof_dir.join("email_config.json");
+    match password_source {
+        PasswordSource::Stdin => {
+            let password = read_password_from_stdin()?;
+            let passphrase = read_passphrase(true)?;
+            email_config.encrypted_password = Some(encrypt_secret(&password, &passphrase)?);
+        }
+        PasswordSource::Env(var) => email_config.password_env = Some(var),
+        PasswordSource::Command(command) => email_config.password_command = Some(command),
+    }
+
+    let mut profiles = load_profiles()?;
+    let name = profile
+        .map(str::to_string)
+        .or_else(|| profiles.default.clone())
+        .unwrap_or_else(|| DEFAULT_PROFILE.to_string());
+
+    profiles.profiles.insert(name.clone(), email_config);
+    if make_default || profiles.default.is_none() {
+        profiles.default = Some(name.clone());
+    }
+
+    let config_path = save_profiles(&profiles)?;
+    println!("Stored email profile '{}' at {}", name, config_path.display());
+    Ok(())
+}
+
+/// Print every profile, marking the default one. Passwords are never shown.
+pub fn list_profiles() -> io::Result<()> {
+    let profiles = load_profiles()?;
+    if profiles.profiles.is_empty() {
+        println!("No email profiles configured; run 'bof config-email' first.");
+        return Ok(());
+    }
+
+    for (name, config) in &profiles.profiles {
+        let marker = if profiles.default.as_deref() == Some(name.as_str()) { "*" } else { " " };
+        println!("{} {}: {} via {}:{}", marker, name, config.address, config.server, config.port);
+    }
+    Ok(())
+}
+
+pub fn remove_profile(name: &str) -> io::Result<()> {
+    let mut profiles = load_profiles()?;
+    if profiles.profiles.remove(name).is_none() {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("No email profile named '{}'", name)));
+    }
+
+    if profiles.default.as_deref() == Some(name) {
+        profiles.default = profiles.profiles.keys().next().cloned();
+        match &profiles.default {
+            Some(new_default) => println!("'{}' is now the default profile", new_default),
+            None => println!("No email profiles left"),
+        }
+    }
 
-    let json_data = serde_json::to_string_pretty(&email_config)
-        .map_err(|e| io::Error::new(io::ErrorKind::Other, format!("Serialize error: {e}")))?;
+    save_profiles(&profiles)?;
+    println!("Removed email profile '{}'", name);
+    Ok(())
+}
 
-    let mut file = fs::File::create(&config_path)?;
-    file.write_all(json_data.as_bytes())?;
+pub fn set_default_profile(name: &str) -> io::Result<()> {
+    let mut profiles = load_profiles()?;
+    if !profiles.profiles.contains_key(name) {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("No email profile named '{}'", name)));
+    }
 
-    println!("Stored email config at {}", config_path.display());
+    profiles.default = Some(name.to_string());
+    save_profiles(&profiles)?;
+    println!("'{}' is now the default email profile", name);
     Ok(())
 }
+
+/// Load the named profile, or the default one, and resolve its password,
+/// whichever way it was stored: legacy plaintext, encrypted, environment
+/// variable or password command.
+pub fn load_email_config(profile: Option<&str>) -> io::Result<EmailConfig> {
+    let mut profiles = load_profiles()?;
+    if profiles.profiles.is_empty() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("Global email config not found at {}", email_config_path()?.display()),
+        ));
+    }
+
+    let name = match profile {
+        Some(name) => name.to_string(),
+        None => profiles.default.clone().ok_or_else(|| {
+            io::Error::new(ErrorKind::NotFound, "No default email profile; pass --profile")
+        })?,
+    };
+    let mut cfg = profiles.profiles.remove(&name).ok_or_else(|| {
+        io::Error::new(ErrorKind::NotFound, format!("No email profile named '{}'", name))
+    })?;
+
+    if cfg.password.is_empty() {
+        if let Some(var) = &cfg.password_env {
+            cfg.password = env::var(var).map_err(|_| {
+                io::Error::new(ErrorKind::NotFound, format!("Environment variable {} is not set", var))
+            })?;
+        } else if let Some(command) = &cfg.password_command {
+            cfg.password = run_password_command(command)?;
+        } else if let Some(secret) = &cfg.encrypted_password {
+            cfg.password = decrypt_secret(secret, &read_passphrase(false)?)?;
+        }
+    }
+
+    Ok(cfg)
+}
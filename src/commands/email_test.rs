@@ -0,0 +1,118 @@
+use std::io::{self, ErrorKind};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use lettre::message::{header::ContentType, Mailbox};
+use lettre::transport::smtp::authentication::{Credentials, DEFAULT_MECHANISMS};
+use lettre::transport::smtp::client::{SmtpConnection, TlsParameters};
+use lettre::transport::smtp::extension::ClientId;
+use lettre::Message;
+
+use crate::commands::email_config::load_email_config;
+use crate::commands::send_email::SMTP_TIMEOUT;
+use crate::data_struct::{EmailConfig, SmtpSecurity};
+
+fn stage_error(stage: &str, config: &EmailConfig, e: impl std::fmt::Display) -> io::Error {
+    let kind = match stage {
+        "connect" => ErrorKind::ConnectionRefused,
+        "TLS" => ErrorKind::ConnectionAborted,
+        "auth" => ErrorKind::PermissionDenied,
+        _ => ErrorKind::Other,
+    };
+    io::Error::new(
+        kind,
+        format!("{} stage failed for {}:{}: {}", stage, config.server, config.port, e),
+    )
+}
+
+fn tls_parameters(config: &EmailConfig) -> io::Result<TlsParameters> {
+    TlsParameters::new(config.server.clone()).map_err(|e| stage_error("TLS", config, e))
+}
+
+/// Open a connection as `send-email` would, printing each stage that succeeds.
+fn connect(config: &EmailConfig, hello: &ClientId) -> io::Result<SmtpConnection> {
+    let address = (config.server.as_str(), config.port);
+
+    match config.security {
+        SmtpSecurity::Tls => {
+            // The handshake happens while connecting, so reach the server first
+            // to tell an unreachable server from a failed handshake.
+            let socket = address
+                .to_socket_addrs()
+                .map_err(|e| stage_error("connect", config, e))?
+                .next()
+                .ok_or_else(|| stage_error("connect", config, "no address found"))?;
+            TcpStream::connect_timeout(&socket, SMTP_TIMEOUT).map_err(|e| stage_error("connect", config, e))?;
+            println!("connect: ok ({}:{})", config.server, config.port);
+
+            let tls = tls_parameters(config)?;
+            let conn = SmtpConnection::connect(address, Some(SMTP_TIMEOUT), hello, Some(&tls), None)
+                .map_err(|e| stage_error("TLS", config, e))?;
+            println!("TLS: ok (implicit)");
+            Ok(conn)
+        }
+        SmtpSecurity::StartTls => {
+            let mut conn = SmtpConnection::connect(address, Some(SMTP_TIMEOUT), hello, None, None)
+                .map_err(|e| stage_error("connect", config, e))?;
+            println!("connect: ok ({}:{})", config.server, config.port);
+            let tls = tls_parameters(config)?;
+            conn.starttls(&tls, hello).map_err(|e| stage_error("TLS", config, e))?;
+            println!("TLS: ok (STARTTLS)");
+            Ok(conn)
+        }
+        SmtpSecurity::Plain => {
+            let conn = SmtpConnection::connect(address, Some(SMTP_TIMEOUT), hello, None, None)
+                .map_err(|e| stage_error("connect", config, e))?;
+            println!("connect: ok ({}:{})", config.server, config.port);
+            println!("TLS: skipped (plain connection)");
+            Ok(conn)
+        }
+    }
+}
+
+/// Check an email profile stage by stage: connect, TLS, authentication and,
+/// when `send_to` is given, sending a short test message.
+pub fn test_email_config(profile: Option<&str>, send_to: Option<&str>) -> io::Result<()> {
+    let recipient = send_to
+        .map(|address| {
+            address.parse::<Mailbox>().map_err(|e| {
+                io::Error::new(ErrorKind::InvalidInput, format!("Invalid email address '{}': {}", address, e))
+            })
+        })
+        .transpose()?;
+
+    let config = load_email_config(profile)?;
+    let hello = ClientId::default();
+    let mut conn = connect(&config, &hello)?;
+
+    if config.password.is_empty() {
+        println!("auth: skipped (no password configured)");
+    } else {
+        let credentials = Credentials::new(config.address.clone(), config.password.clone());
+        conn.auth(DEFAULT_MECHANISMS, &credentials)
+            .map_err(|e| stage_error("auth", &config, e))?;
+        println!("auth: ok ({})", config.address);
+    }
+
+    if let Some(recipient) = recipient {
+        let from = config.address.parse::<Mailbox>().map_err(|e| {
+            io::Error::new(ErrorKind::InvalidInput, format!("Invalid email address '{}': {}", config.address, e))
+        })?;
+        let message = Message::builder()
+            .from(from)
+            .to(recipient.clone())
+            .subject("bof test message")
+            .header(ContentType::TEXT_PLAIN)
+            .body(format!(
+                "This is a test message from bof, sent through {}:{}.\n",
+                config.server, config.port
+            ))
+            .map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("Could not build email: {e}")))?;
+
+        conn.send(message.envelope(), &message.formatted())
+            .map_err(|e| stage_error("send", &config, e))?;
+        println!("send: ok (test message to {})", recipient);
+    }
+
+    let _ = conn.quit();
+    Ok(())
+}
@@ -1,13 +1,16 @@
 // src/commands/find_file.rs
 use std::fs;
-use std::path::Path;
-use std::io;
+use std::path::{Path, PathBuf};
+use std::io::{self, ErrorKind};
+use filetime::FileTime;
 use sha2::{Sha256, Digest};
 use serde_json;
 use crate::commands::global::get_global_bof_dir;
+use crate::commands::index::{canonicalize_path, load_metadata, stored_path};
+use crate::data_struct::FileMetadata;
 
-/// Find all directories where a file appears
-pub fn find_file_directories(file_hash: &str) -> io::Result<Vec<String>> {
+/// Find all directories where a file appears, given its `sha256:<hex>` key
+pub fn find_file_directories(file_key: &str) -> io::Result<Vec<String>> {
     let global_bof_dir = get_global_bof_dir()?;
     let inverse_table_path = global_bof_dir.join("inverse_table.json");
 
@@ -18,8 +21,7 @@ pub fn find_file_directories(file_hash: &str) -> io::Result<Vec<String>> {
     let data = fs::read_to_string(&inverse_table_path)?;
     let inverse_table: serde_json::Value = serde_json::from_str(&data)?;
 
-    let file_key = format!("sha256:{}", file_hash);
-    if let Some(file_entry) = inverse_table["files"].get(&file_key) {
+    if let Some(file_entry) = inverse_table["files"].get(file_key) {
         let directories = file_entry["directories"]
             .as_array()
             .unwrap()
@@ -40,3 +42,67 @@ pub fn compute_file_hash(path: &Path) -> io::Result<String> {
     let hash = hasher.finalize();
     Ok(format!("{:x}", hash))
 }
+
+/// Return the `.bof` directory of the closest indexed root containing `file_path`
+pub fn find_bof_dir_for_path(file_path: &Path) -> Option<PathBuf> {
+    let mut current = file_path.parent();
+
+    while let Some(dir) = current {
+        let candidate = dir.join(".bof");
+        if candidate.is_dir() {
+            return Some(candidate);
+        }
+        current = dir.parent();
+    }
+    None
+}
+
+/// Look up the `files.json` record of a file in its enclosing indexed root
+pub fn find_indexed_file(file_path: &Path) -> io::Result<FileMetadata> {
+    let abs_file = canonicalize_path(file_path)?;
+
+    let bof_dir = find_bof_dir_for_path(&abs_file).ok_or_else(|| {
+        io::Error::new(
+            ErrorKind::NotFound,
+            format!("No .bof folder found for file: {}", abs_file.display()),
+        )
+    })?;
+
+    let files_json = bof_dir.join("files.json");
+    if !files_json.exists() {
+        return Err(io::Error::new(
+            ErrorKind::NotFound,
+            format!("{} not found; did you run 'bof index'?", files_json.display()),
+        ));
+    }
+
+    let root = bof_dir.parent().unwrap_or_else(|| Path::new("."));
+    let wanted = stored_path(root, &abs_file);
+    let (all_files, _) = load_metadata(&bof_dir)?;
+
+    all_files
+        .into_iter()
+        .find(|m| m.path == wanted)
+        .ok_or_else(|| {
+            io::Error::new(
+                ErrorKind::NotFound,
+                format!("File '{}' not found in {}", abs_file.display(), files_json.display()),
+            )
+        })
+}
+
+/// Hash of `path` with its algorithm prefix, e.g. `sha256:<hex>`. Reuses the hash
+/// stored in the local index when the file's size and mtime (to the nanosecond) still match it.
+pub fn file_hash_key(path: &Path) -> io::Result<String> {
+    if let Ok(indexed) = find_indexed_file(path) {
+        let metadata = fs::metadata(path)?;
+        let mtime = FileTime::from_last_modification_time(&metadata);
+        if let Some(hash) = indexed.hash {
+            let same_mtime = indexed.mtime == mtime.unix_seconds() as u64 && indexed.mtime_nsec == mtime.nanoseconds();
+            if indexed.size == metadata.len() && same_mtime {
+                return Ok(hash);
+            }
+        }
+    }
+    Ok(format!("sha256:{}", compute_file_hash(path)?))
+}
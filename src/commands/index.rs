@@ -1,25 +1,38 @@
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::io::{self};
-use std::collections::HashMap;
+use std::time::Duration;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::thread;
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use filetime::FileTime;
 use uuid::Uuid;
-use walkdir::WalkDir;
+use ignore::WalkBuilder;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
 use sha2::{Sha256, Digest};
 use serde_json::json;
 
-use crate::commands::global::get_global_bof_dir;
-use crate::data_struct::{FileMetadata, DirectoryMetadata};
+use crate::commands::content::update_content_index;
+use crate::commands::name_index::{source_fingerprint, update_name_index};
+use crate::commands::global::{get_global_bof_dir, write_atomic};
+use crate::commands::lock::lock_dir;
+use crate::data_struct::{FileMetadata, DirectoryEntry, DirectoryMetadata};
+
+/// File in `.bof` holding the namespace entry keys are derived from.
+const NAMESPACE_FILE: &str = "namespace";
 
 pub fn canonicalize_path(path: &Path) -> io::Result<PathBuf> {
     let canonical_path = fs::canonicalize(path)?;
-    let canonical_str = canonical_path.to_string_lossy().to_string();
 
     #[cfg(windows)]
-    let cleaned_path = if canonical_str.starts_with(r"\\?\") {
-        PathBuf::from(&canonical_str[4..])
-    } else {
-        canonical_path
+    let cleaned_path = {
+        let canonical_str = canonical_path.to_string_lossy().to_string();
+        if canonical_str.starts_with(r"\\?\") {
+            PathBuf::from(&canonical_str[4..])
+        } else {
+            canonical_path
+        }
     };
 
     #[cfg(not(windows))]
@@ -28,37 +41,130 @@ pub fn canonicalize_path(path: &Path) -> io::Result<PathBuf> {
     Ok(cleaned_path)
 }
 
-fn update_inverse_table(file_path: &Path, file_name: &str, file_hash: &str) -> io::Result<()> {
-    let global_bof_dir = get_global_bof_dir()?;
-    let inverse_table_path = global_bof_dir.join("inverse_table.json");
-
-    let mut inverse_table: serde_json::Value = if inverse_table_path.exists() {
-        let data = fs::read_to_string(&inverse_table_path)?;
-        serde_json::from_str(&data).unwrap_or(json!({ "files": {} }))
+pub fn load_inverse_table(inverse_table_path: &Path) -> io::Result<serde_json::Value> {
+    if inverse_table_path.exists() {
+        let data = fs::read_to_string(inverse_table_path)?;
+        Ok(serde_json::from_str(&data).unwrap_or(json!({ "files": {} })))
     } else {
-        json!({ "files": {} })
-    };
+        Ok(json!({ "files": {} }))
+    }
+}
+
+/// Every root folder that has files registered in the global inverse table.
+pub fn indexed_roots() -> io::Result<BTreeSet<PathBuf>> {
+    let inverse_table = load_inverse_table(&get_global_bof_dir()?.join("inverse_table.json"))?;
+    Ok(inverse_table_roots(&inverse_table))
+}
 
-    let absolute_path = canonicalize_path(file_path)?;
-    let parent_dir = absolute_path.parent().unwrap_or_else(|| Path::new("."));
+/// The root folders listed in `inverse_table`.
+pub fn inverse_table_roots(inverse_table: &serde_json::Value) -> BTreeSet<PathBuf> {
+    let mut roots = BTreeSet::new();
 
-    let file_key = format!("sha256:{}", file_hash);
-    if let Some(file_entry) = inverse_table["files"].get_mut(&file_key) {
-        let directories = file_entry["directories"].as_array_mut().unwrap();
-        if !directories.contains(&serde_json::Value::String(parent_dir.to_string_lossy().to_string())) {
-            directories.push(serde_json::Value::String(parent_dir.to_string_lossy().to_string()));
+    if let Some(files) = inverse_table["files"].as_object() {
+        for entry in files.values() {
+            if let Some(directories) = entry["directories"].as_array() {
+                roots.extend(directories.iter().filter_map(|d| d.as_str()).map(PathBuf::from));
+            }
         }
-    } else {
-        inverse_table["files"][&file_key] = json!({
-            "name": file_name,
-            "directories": [parent_dir.to_string_lossy().to_string()]
+    }
+    roots
+}
+
+/// Record that `root` holds the file `file_key` under `names`, replacing the
+/// names recorded for `root` by earlier runs.
+fn update_inverse_table(inverse_table: &mut serde_json::Value, root: &str, file_key: &str, names: &BTreeSet<String>) {
+    let file_entry = &mut inverse_table["files"][file_key];
+    if file_entry.is_null() {
+        *file_entry = json!({ "directories": [], "names": {} });
+    }
+
+    let directories = file_entry["directories"].as_array_mut().unwrap();
+    if !directories.contains(&serde_json::Value::String(root.to_string())) {
+        directories.push(serde_json::Value::String(root.to_string()));
+    }
+    if !file_entry["names"].is_object() {
+        file_entry["names"] = json!({});
+    }
+    file_entry["names"][root] = json!(names);
+}
+
+/// Drop this root from every inverse table entry whose hash it no longer holds,
+/// and delete entries that end up with no directories at all.
+fn prune_inverse_table(inverse_table: &mut serde_json::Value, root: &str, live_hashes: &HashSet<String>) {
+    let root_value = serde_json::Value::String(root.to_string());
+
+    if let Some(files) = inverse_table["files"].as_object_mut() {
+        files.retain(|file_key, file_entry| {
+            if live_hashes.contains(file_key) {
+                return true;
+            }
+            if let Some(names) = file_entry["names"].as_object_mut() {
+                names.remove(root);
+            }
+            match file_entry["directories"].as_array_mut() {
+                Some(directories) => {
+                    directories.retain(|d| d != &root_value);
+                    !directories.is_empty()
+                }
+                None => true,
+            }
         });
     }
+}
 
-    let inverse_table_json = serde_json::to_string_pretty(&inverse_table)?;
-    fs::write(inverse_table_path, inverse_table_json)?;
+/// Knobs for a single index run.
+#[derive(Debug, Default, Clone)]
+pub struct IndexOptions {
+    /// How long to wait for another bof process to release its locks.
+    pub lock_wait: Option<Duration>,
+    /// Number of hashing threads; `None` uses one per available CPU.
+    pub jobs: Option<usize>,
+    /// Also honor `.gitignore` files, on top of `.bofignore`.
+    pub use_gitignore: bool,
+    /// Extra gitignore-style patterns for this run only.
+    pub excludes: Vec<String>,
+    /// Build the full-text content index for text files up to this many bytes.
+    /// An existing content index is kept up to date either way.
+    pub content_max_size: Option<u64>,
+}
 
-    Ok(())
+pub fn default_jobs() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Counts reported at the end of an index run.
+#[derive(Debug, Default)]
+pub struct IndexSummary {
+    pub rehashed: usize,
+    pub skipped: usize,
+    pub removed: usize,
+    /// Files whose words were (re)indexed, when the root has a content index.
+    pub content_indexed: Option<usize>,
+}
+
+#[cfg(unix)]
+fn file_identity(metadata: &fs::Metadata) -> (Option<u64>, Option<u64>) {
+    use std::os::unix::fs::MetadataExt;
+    (Some(metadata.ino()), Some(metadata.dev()))
+}
+
+#[cfg(not(unix))]
+fn file_identity(_metadata: &fs::Metadata) -> (Option<u64>, Option<u64>) {
+    (None, None)
+}
+
+/// A stored entry can be reused when size and mtime (to the nanosecond) match,
+/// and inode/device match too whenever both sides recorded them.
+fn is_unchanged(existing: &FileMetadata, size: u64, mtime: FileTime, inode: Option<u64>, device: Option<u64>) -> bool {
+    let same_identity = match (existing.inode, existing.device, inode, device) {
+        (Some(old_ino), Some(old_dev), Some(ino), Some(dev)) => old_ino == ino && old_dev == dev,
+        _ => true,
+    };
+    existing.hash.is_some()
+        && existing.size == size
+        && existing.mtime == mtime.unix_seconds() as u64
+        && existing.mtime_nsec == mtime.nanoseconds()
+        && same_identity
 }
 
 fn compute_file_hash(path: &Path) -> io::Result<String> {
@@ -69,7 +175,185 @@ fn compute_file_hash(path: &Path) -> io::Result<String> {
     Ok(format!("{:x}", hash))
 }
 
-fn load_metadata(bof_dir: &Path) -> io::Result<(Vec<FileMetadata>, Vec<DirectoryMetadata>)> {
+/// Paths in files.json are stored relative to the indexed root, as `./sub/file`.
+/// The root itself is stored as `.`.
+pub fn stored_path(dir: &Path, path: &Path) -> String {
+    match path.strip_prefix(dir) {
+        Ok(relative) if relative.as_os_str().is_empty() => ".".to_string(),
+        Ok(relative) => Path::new(".").join(relative).to_string_lossy().to_string(),
+        Err(_) => path.to_string_lossy().to_string(),
+    }
+}
+
+/// Where the file stored as `stored_path` under `root` is on disk.
+pub fn location(root: &Path, stored_path: &str) -> String {
+    root.join(stored_path.trim_start_matches("./")).to_string_lossy().to_string()
+}
+
+fn parent_path(stored_path: &str) -> Option<String> {
+    Path::new(stored_path)
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(|p| p.to_string_lossy().to_string())
+}
+
+fn entry_name(stored_path: &str) -> String {
+    Path::new(stored_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Link directories and files into a tree: each directory record gets its parent's
+/// key and one entry per indexed child, pointing at the child's own key.
+fn build_directory_tree(
+    namespace: &Uuid,
+    dir_paths: &[String],
+    files: &BTreeMap<String, FileMetadata>,
+) -> Vec<DirectoryMetadata> {
+    let mut taken: HashSet<String> = files.values().map(|f| f.key.clone()).collect();
+    let mut dir_keys: HashMap<String, String> = HashMap::new();
+    for path in dir_paths {
+        let key = unique_entry_key(namespace, path, &taken);
+        taken.insert(key.clone());
+        dir_keys.insert(path.clone(), key);
+    }
+
+    let mut dirs: BTreeMap<String, DirectoryMetadata> = dir_keys
+        .iter()
+        .map(|(path, key)| {
+            let dir_data = DirectoryMetadata {
+                key: key.clone(),
+                path: path.clone(),
+                parent: parent_path(path).and_then(|p| dir_keys.get(&p).cloned()),
+                entries: Vec::new(),
+            };
+            (path.clone(), dir_data)
+        })
+        .collect();
+
+    let dir_children = dir_keys.iter().map(|(path, key)| (path, key, "directory".to_string()));
+    let file_children = files.values().map(|f| (&f.path, &f.key, f.file_type.clone()));
+    for (path, key, kind) in dir_children.chain(file_children) {
+        if let Some(parent) = parent_path(path).and_then(|p| dirs.get_mut(&p)) {
+            parent.entries.push(DirectoryEntry {
+                key: key.clone(),
+                kind,
+                name: entry_name(path),
+            });
+        }
+    }
+
+    let mut tree: Vec<DirectoryMetadata> = dirs.into_values().collect();
+    for dir in &mut tree {
+        dir.entries.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+    tree
+}
+
+/// The namespace this root's keys are derived from: a random UUID generated on
+/// the first index run and kept in `.bof/namespace`, so that the same path
+/// under two roots gets different keys.
+fn root_namespace(bof_dir: &Path) -> io::Result<Uuid> {
+    let path = bof_dir.join(NAMESPACE_FILE);
+    match fs::read_to_string(&path) {
+        Ok(text) => Uuid::parse_str(text.trim()).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("Invalid key namespace in {}: {}", path.display(), e))
+        }),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            let namespace = Uuid::new_v4();
+            write_atomic(&path, namespace.to_string().as_bytes())?;
+            Ok(namespace)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Deterministic key for an entry, derived from its root's namespace and its
+/// stored path so that it stays the same across re-indexes.
+fn entry_key(namespace: &Uuid, stored_path: &str) -> String {
+    Uuid::new_v5(namespace, stored_path.as_bytes()).to_string()
+}
+
+/// Like `entry_key`, but steps around keys already held by other entries (e.g. a
+/// moved file that kept the key its old path would have produced).
+fn unique_entry_key(namespace: &Uuid, stored_path: &str, taken: &HashSet<String>) -> String {
+    let mut key = entry_key(namespace, stored_path);
+    let mut n = 1;
+    while taken.contains(&key) {
+        key = entry_key(namespace, &format!("{}#{}", stored_path, n));
+        n += 1;
+    }
+    key
+}
+
+/// Patterns passed with `--exclude`, matched relative to the indexed root.
+fn build_excludes(dir: &Path, excludes: &[String]) -> io::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(dir);
+    for pattern in excludes {
+        builder
+            .add_line(None, pattern)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid exclude pattern '{}': {}", pattern, e)))?;
+    }
+    builder
+        .build()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid exclude patterns: {}", e)))
+}
+
+/// Walk `dir` honoring `.bofignore` files (and `.gitignore` files when asked),
+/// never descending into the `.bof` directory itself.
+fn build_walker(dir: &Path, bof_dir: &Path, options: &IndexOptions) -> io::Result<ignore::Walk> {
+    let excludes = build_excludes(dir, &options.excludes)?;
+    let bof_dir = bof_dir.to_path_buf();
+
+    let mut builder = WalkBuilder::new(dir);
+    builder
+        .standard_filters(false)
+        .parents(false)
+        .add_custom_ignore_filename(".bofignore")
+        .git_ignore(options.use_gitignore)
+        .require_git(false)
+        .filter_entry(move |entry| {
+            if entry.path().starts_with(&bof_dir) {
+                return false;
+            }
+            let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+            !excludes.matched(entry.path(), is_dir).is_ignore()
+        });
+
+    Ok(builder.build())
+}
+
+/// Hash `paths` on a pool of up to `jobs` threads. Each worker takes the next
+/// unclaimed path, so one large file doesn't hold up the rest, and writes its
+/// result into that path's slot: results come back in the same order as `paths`.
+fn hash_files(paths: &[PathBuf], jobs: usize) -> Vec<io::Result<String>> {
+    let jobs = jobs.clamp(1, paths.len().max(1));
+    if jobs == 1 {
+        return paths.iter().map(|p| compute_file_hash(p)).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let slots: Vec<OnceLock<io::Result<String>>> = paths.iter().map(|_| OnceLock::new()).collect();
+    thread::scope(|scope| {
+        for _ in 0..jobs {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some(path) = paths.get(i) else {
+                    break;
+                };
+                let _ = slots[i].set(compute_file_hash(path));
+            });
+        }
+    });
+
+    slots
+        .into_iter()
+        .map(|slot| slot.into_inner().expect("every path was claimed by a worker"))
+        .collect()
+}
+
+pub fn load_metadata(bof_dir: &Path) -> io::Result<(Vec<FileMetadata>, Vec<DirectoryMetadata>)> {
     let file_metadata_path = bof_dir.join("files.json");
     let dir_metadata_path = bof_dir.join("directories.json");
 
@@ -97,19 +381,44 @@ fn save_metadata(bof_dir: &Path, file_metadata: &[FileMetadata], dir_metadata: &
     let file_metadata_json = serde_json::to_string_pretty(file_metadata)?;
     let dir_metadata_json = serde_json::to_string_pretty(dir_metadata)?;
 
-    fs::write(file_metadata_path, file_metadata_json)?;
-    fs::write(dir_metadata_path, dir_metadata_json)?;
+    write_atomic(&file_metadata_path, file_metadata_json.as_bytes())?;
+    write_atomic(&dir_metadata_path, dir_metadata_json.as_bytes())?;
 
     Ok(())
 }
 
-pub fn collect_metadata(dir: &Path, bof_dir: &Path) -> io::Result<()> {
-    let (existing_files, existing_dirs) = load_metadata(bof_dir)?;
-
-    let mut file_map: HashMap<String, FileMetadata> = existing_files.into_iter().map(|f| (f.path.clone(), f)).collect();
-    let mut dir_map: HashMap<String, DirectoryMetadata> = existing_dirs.into_iter().map(|d| (d.key.clone(), d)).collect();
+pub fn collect_metadata(dir: &Path, bof_dir: &Path, options: &IndexOptions) -> io::Result<IndexSummary> {
+    if !bof_dir.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist; run 'bof init' first", bof_dir.display()),
+        ));
+    }
 
-    for entry in WalkDir::new(dir) {
+    let _local_lock = lock_dir(bof_dir, options.lock_wait)?;
+    let namespace = root_namespace(bof_dir)?;
+    let (existing_files, _) = load_metadata(bof_dir)?;
+    // What the name index holds for this root, so only the differences are added to it.
+    let previous_hashes: Option<HashMap<String, String>> = (!existing_files.is_empty())
+        .then(|| existing_files.iter().filter_map(|f| Some((f.path.clone(), f.hash.clone()?))).collect());
+
+    let absolute_bof_dir = canonicalize_path(bof_dir)?;
+    let root = absolute_bof_dir
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+        .to_string_lossy()
+        .to_string();
+
+    // Ordered maps keep files.json, directories.json and the inverse table deterministic.
+    let mut file_map: BTreeMap<String, FileMetadata> = existing_files.into_iter().map(|f| (f.path.clone(), f)).collect();
+    // Directory records are cheap to rebuild, so start from scratch to drop vanished ones.
+    let mut dir_paths: Vec<String> = Vec::new();
+    let mut seen_paths: HashSet<String> = HashSet::new();
+    let mut to_hash: Vec<(PathBuf, FileMetadata)> = Vec::new();
+    let mut new_files = Vec::new();
+    let mut summary = IndexSummary::default();
+
+    for entry in build_walker(dir, bof_dir, options)? {
         let entry = match entry {
             Ok(e) => e,
             Err(err) => {
@@ -118,10 +427,6 @@ pub fn collect_metadata(dir: &Path, bof_dir: &Path) -> io::Result<()> {
             }
         };
 
-        if entry.path().starts_with(bof_dir) {
-            continue;
-        }
-
         let metadata = match fs::symlink_metadata(entry.path()) {
             Ok(m) => m,
             Err(err) => {
@@ -138,57 +443,262 @@ pub fn collect_metadata(dir: &Path, bof_dir: &Path) -> io::Result<()> {
             "other".to_string()
         };
 
-        let mtime = FileTime::from_last_modification_time(&metadata).unix_seconds();
+        let mtime = FileTime::from_last_modification_time(&metadata);
 
         let ctime = FileTime::from_creation_time(&metadata)
             .map(|t| t.unix_seconds())
             .unwrap_or(0); 
 
         let size = metadata.len();
-
-        let key = Uuid::new_v4().to_string();
+        let (inode, device) = file_identity(&metadata);
+        let path = stored_path(dir, entry.path());
 
         if metadata.is_dir() {
-            let entries = fs::read_dir(entry.path())
-                .unwrap()
-                .map(|e| {
-                    let e = e.unwrap();
-                    let kind = if e.metadata().unwrap().is_dir() {
-                        "directory".to_string()
-                    } else {
-                        "file".to_string()
-                    };
-                    (key.clone(), kind, e.file_name().to_string_lossy().to_string())
-                })
-                .collect();
-
-            let dir_data = DirectoryMetadata {
-                key: key.clone(),
-                entries,
-            };
-            dir_map.insert(key, dir_data);
+            dir_paths.push(path);
         } else {
-            let file_hash = compute_file_hash(entry.path())?;
-            let file_name = entry.file_name().to_string_lossy().to_string();
-
-            update_inverse_table(bof_dir, &file_name, &file_hash)?;
+            seen_paths.insert(path.clone());
+            let key = match file_map.get(&path) {
+                Some(existing) if is_unchanged(existing, size, mtime, inode, device) => {
+                    summary.skipped += 1;
+                    continue;
+                }
+                Some(existing) => existing.key.clone(),
+                // Assigned once all surviving keys are known, see below.
+                None => String::new(),
+            };
 
             let file_data = FileMetadata {
                 key,
-                path: entry.path().to_string_lossy().to_string(),
+                path,
                 file_type,
                 ctime: ctime as u64,
-                mtime: mtime as u64,
+                mtime: mtime.unix_seconds() as u64,
+                mtime_nsec: mtime.nanoseconds(),
                 size,
+                hash: None,
+                inode,
+                device,
             };
-            file_map.insert(file_data.path.clone(), file_data);
+            // Only regular files are read: a symlink may point at a directory and a
+            // FIFO blocks until something writes to it. Other entries are recorded
+            // without a hash.
+            if !metadata.is_file() {
+                if file_data.key.is_empty() {
+                    new_files.push(file_data);
+                } else {
+                    file_map.insert(file_data.path.clone(), file_data);
+                }
+                continue;
+            }
+            to_hash.push((entry.path().to_path_buf(), file_data));
+        }
+    }
+
+    // A new path carrying the inode and content of a vanished entry was renamed or
+    // moved, so it keeps that entry's key. The content has to match too: a freed
+    // inode is often reused by an unrelated new file.
+    let mut vanished: HashMap<(u64, u64), (String, Option<String>)> = file_map
+        .values()
+        .filter(|f| !seen_paths.contains(&f.path))
+        .filter_map(|f| Some(((f.inode?, f.device?), (f.key.clone(), f.hash.clone()))))
+        .collect();
+
+    let paths: Vec<PathBuf> = to_hash.iter().map(|(p, _)| p.clone()).collect();
+    let hashes = hash_files(&paths, options.jobs.unwrap_or_else(default_jobs));
+    for ((_, mut file_data), file_hash) in to_hash.into_iter().zip(hashes) {
+        let file_hash = match file_hash {
+            Ok(hash) => hash,
+            // Deleted between the walk and hashing: handled like any other removal.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                seen_paths.remove(&file_data.path);
+                continue;
+            }
+            // Unreadable (e.g. no permission): keep whatever was stored for it.
+            Err(e) => {
+                eprintln!("Could not hash {:?}, skipping it: {}", file_data.path, e);
+                continue;
+            }
+        };
+        file_data.hash = Some(format!("sha256:{}", file_hash));
+        summary.rehashed += 1;
+        if file_data.key.is_empty() {
+            let moved_key = file_data
+                .inode
+                .zip(file_data.device)
+                .and_then(|identity| vanished.remove(&identity))
+                .filter(|(_, hash)| *hash == file_data.hash)
+                .map(|(key, _)| key);
+            match moved_key {
+                Some(key) => file_data.key = key,
+                None => {
+                    new_files.push(file_data);
+                    continue;
+                }
+            }
         }
+        file_map.insert(file_data.path.clone(), file_data);
     }
 
+    let before = file_map.len() + new_files.len();
+    file_map.retain(|path, _| seen_paths.contains(path));
+
+    let mut taken: HashSet<String> = file_map.values().map(|f| f.key.clone()).collect();
+    for mut file_data in new_files {
+        file_data.key = unique_entry_key(&namespace, &file_data.path, &taken);
+        taken.insert(file_data.key.clone());
+        file_map.insert(file_data.path.clone(), file_data);
+    }
+    summary.removed = before - file_map.len();
+
+    // Hold the global lock only while merging into the inverse table and name index,
+    // re-reading the table so that changes committed by other index runs in the
+    // meantime are kept.
+    let global_bof_dir = get_global_bof_dir()?;
+    let global_lock = lock_dir(&global_bof_dir, options.lock_wait)?;
+    let inverse_table_path = global_bof_dir.join("inverse_table.json");
+    let previous_source = source_fingerprint(&inverse_table_path)?;
+    let mut inverse_table = load_inverse_table(&inverse_table_path)?;
+
+    // Names are recomputed from this root's live files, so renames and copies
+    // under another name are reflected.
+    let mut names_by_hash: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+    for file in file_map.values() {
+        if let Some(hash) = file.hash.as_deref() {
+            names_by_hash.entry(hash).or_default().insert(entry_name(&file.path));
+        }
+    }
+    for (file_key, names) in &names_by_hash {
+        update_inverse_table(&mut inverse_table, &root, file_key, names);
+    }
+
+    let live_hashes: HashSet<String> = file_map.values().filter_map(|f| f.hash.clone()).collect();
+    prune_inverse_table(&mut inverse_table, &root, &live_hashes);
+
+    let updated_dirs = build_directory_tree(&namespace, &dir_paths, &file_map);
     let updated_files: Vec<FileMetadata> = file_map.into_values().collect();
-    let updated_dirs: Vec<DirectoryMetadata> = dir_map.into_values().collect();
 
+    // Commit all global changes from this run in a single atomic write, before the
+    // local index, so an interrupted run is simply redone on the next one.
+    let inverse_table_json = serde_json::to_string_pretty(&inverse_table)?;
+    write_atomic(&inverse_table_path, inverse_table_json.as_bytes())?;
     save_metadata(bof_dir, &updated_files, &updated_dirs)?;
+    // The name index records which inverse table it matches and is rebuilt from
+    // every files.json if it falls behind, so it is updated last; until then
+    // search falls back to a full scan.
+    update_name_index(
+        &global_bof_dir,
+        &inverse_table_roots(&inverse_table),
+        previous_source,
+        &root,
+        previous_hashes.as_ref(),
+        &updated_files,
+    )?;
+    drop(global_lock);
+
+    // Reading file contents can take a while, so it runs under the local lock only.
+    summary.content_indexed = update_content_index(Path::new(&root), bof_dir, &updated_files, options.content_max_size)?;
+
+    Ok(summary)
+}
 
-    Ok(())
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, hash: Option<&str>) -> FileMetadata {
+        FileMetadata {
+            key: path.to_string(),
+            path: path.to_string(),
+            file_type: "file".to_string(),
+            ctime: 1,
+            mtime: 1_700_000_000,
+            mtime_nsec: 500,
+            size: 42,
+            hash: hash.map(str::to_string),
+            inode: Some(7),
+            device: Some(1),
+        }
+    }
+
+    #[test]
+    fn is_unchanged_compares_size_mtime_and_identity() {
+        let existing = file("./a.txt", Some("sha256:1"));
+        let mtime = FileTime::from_unix_time(1_700_000_000, 500);
+        assert!(is_unchanged(&existing, 42, mtime, Some(7), Some(1)));
+        assert!(is_unchanged(&existing, 42, mtime, None, None), "identity is only compared when known");
+
+        assert!(!is_unchanged(&existing, 43, mtime, Some(7), Some(1)));
+        assert!(!is_unchanged(&existing, 42, FileTime::from_unix_time(1_700_000_000, 501), Some(7), Some(1)));
+        assert!(!is_unchanged(&existing, 42, FileTime::from_unix_time(1_700_000_001, 500), Some(7), Some(1)));
+        assert!(!is_unchanged(&existing, 42, mtime, Some(8), Some(1)));
+        assert!(!is_unchanged(&file("./a.txt", None), 42, mtime, Some(7), Some(1)), "unhashed entries are redone");
+    }
+
+    #[test]
+    fn build_directory_tree_links_parents_and_children() {
+        let dir_paths = vec![".".to_string(), "./sub".to_string()];
+        let files: BTreeMap<String, FileMetadata> = ["./b.txt", "./sub/a.txt"]
+            .into_iter()
+            .map(|p| (p.to_string(), file(p, Some("sha256:1"))))
+            .collect();
+        let tree = build_directory_tree(&Uuid::new_v4(), &dir_paths, &files);
+
+        let root = tree.iter().find(|d| d.path == ".").unwrap();
+        let sub = tree.iter().find(|d| d.path == "./sub").unwrap();
+        assert_eq!(root.parent, None);
+        assert_eq!(sub.parent.as_deref(), Some(root.key.as_str()));
+
+        let root_entries: Vec<(&str, &str)> = root.entries.iter().map(|e| (e.name.as_str(), e.kind.as_str())).collect();
+        assert_eq!(root_entries, [("b.txt", "file"), ("sub", "directory")]);
+        assert_eq!(root.entries[1].key, sub.key);
+        assert_eq!(sub.entries.len(), 1);
+        assert_eq!(sub.entries[0].key, files["./sub/a.txt"].key);
+    }
+
+    #[test]
+    fn build_directory_tree_keeps_directory_keys_apart_from_file_keys() {
+        // A file that moved kept the key a directory at its old path would get.
+        let mut moved = file("./x.txt", Some("sha256:1"));
+        let namespace = Uuid::new_v4();
+        moved.key = entry_key(&namespace, "./old");
+        let files = BTreeMap::from([("./x.txt".to_string(), moved)]);
+        let tree = build_directory_tree(&namespace, &["./old".to_string()], &files);
+        assert_ne!(tree[0].key, entry_key(&namespace, "./old"));
+    }
+
+    #[test]
+    fn entry_keys_differ_between_roots() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(entry_key(&a, "./report.pdf"), entry_key(&a, "./report.pdf"));
+        assert_ne!(entry_key(&a, "./report.pdf"), entry_key(&b, "./report.pdf"));
+    }
+
+    #[test]
+    fn root_namespace_is_generated_once() {
+        let bof_dir = std::env::temp_dir().join(format!("bof-namespace-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&bof_dir);
+        fs::create_dir_all(&bof_dir).unwrap();
+
+        let namespace = root_namespace(&bof_dir).unwrap();
+        assert_eq!(root_namespace(&bof_dir).unwrap(), namespace);
+        fs::write(bof_dir.join(NAMESPACE_FILE), "not a uuid").unwrap();
+        assert_eq!(root_namespace(&bof_dir).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&bof_dir).unwrap();
+    }
+
+    #[test]
+    fn prune_inverse_table_drops_the_root_from_dead_hashes() {
+        let mut table = json!({ "files": {
+            "sha256:live": { "directories": ["/a", "/b"], "names": { "/a": ["x"], "/b": ["y"] } },
+            "sha256:shared": { "directories": ["/a", "/b"], "names": { "/a": ["x"], "/b": ["y"] } },
+            "sha256:only": { "directories": ["/a"], "names": { "/a": ["z"] } },
+        }});
+        prune_inverse_table(&mut table, "/a", &HashSet::from(["sha256:live".to_string()]));
+
+        let files = table["files"].as_object().unwrap();
+        assert_eq!(files["sha256:live"]["directories"], json!(["/a", "/b"]));
+        assert_eq!(files["sha256:shared"], json!({ "directories": ["/b"], "names": { "/b": ["y"] } }));
+        assert!(!files.contains_key("sha256:only"));
+    }
+}
@@ -0,0 +1,44 @@
+use std::fs::{File, TryLockError};
+use std::io;
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const LOCK_FILE: &str = ".lock";
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// An advisory lock on a `.bof` or `bof_global` directory, released when dropped.
+pub struct DirLock {
+    _file: File,
+}
+
+/// Take the exclusive lock for `dir`. With `wait` set, keep retrying until it
+/// elapses; otherwise fail straight away if another bof process holds the lock.
+pub fn lock_dir(dir: &Path, wait: Option<Duration>) -> io::Result<DirLock> {
+    let file = File::options()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(dir.join(LOCK_FILE))?;
+
+    let deadline = wait.map(|w| Instant::now() + w);
+    loop {
+        match file.try_lock() {
+            Ok(()) => return Ok(DirLock { _file: file }),
+            Err(TryLockError::Error(e)) => return Err(e),
+            Err(TryLockError::WouldBlock) => {
+                if deadline.is_some_and(|d| Instant::now() < d) {
+                    thread::sleep(POLL_INTERVAL);
+                    continue;
+                }
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!(
+                        "Another bof process is running (lock held on {}). Retry later or pass --wait <SECONDS>.",
+                        dir.display()
+                    ),
+                ));
+            }
+        }
+    }
+}
@@ -2,7 +2,20 @@ pub mod init;
 pub mod index;
 pub mod find_file;
 pub mod global;
+pub mod lock;
 pub mod clear; 
 pub mod search; 
+pub mod query;
 pub mod email_config;
-pub mod send_email;
\ No newline at end of file
+pub mod send_email;
+pub mod email_test;
+pub mod outbox;
+pub mod digest;
+pub mod notify;
+pub mod webhook;
+pub mod secret;
+pub mod template;
+pub mod units;
+pub mod attachment;
+pub mod content;
+pub mod name_index;
@@ -0,0 +1,773 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use regex_syntax::hir::{Hir, HirKind};
+
+use crate::commands::global::write_atomic;
+use crate::commands::index::load_metadata;
+use crate::data_struct::FileMetadata;
+
+// `~/bof_global/name_index.bin` is a trigram index over the path of every
+// indexed file, relative to its root. Unlike the other bof files it is
+// binary, so a search can read just the lists it needs instead of parsing
+// millions of entries. Layout, with little-endian integers:
+//
+//   header       "BOFNIDX2", inverse table size (u64) and mtime in ns (u64),
+//                entry count (u32), trigram count (u32)
+//   trigrams     sorted; per trigram its UTF-8 zero-padded to 12 bytes, the
+//                offset (u64) and length (u32) of its id list
+//   entries      per id the offset (u64) and length (u32) of the entry
+//   id lists     ascending u32 ids
+//   entry data   hash, root and stored path, separated by NUL bytes
+//
+// Index runs don't rewrite it: they append their changes to
+// `name_index.log`, and the two are merged into a new `name_index.bin` once
+// the log has grown to a fair share of its size. A log record is a tag byte,
+// a u32 length and a payload:
+//
+//   '+'  an entry, encoded as in the entry data
+//   '-'  root and stored path of a removed entry
+//   'x'  a root whose entries in name_index.bin are all gone
+//   '='  the inverse table fingerprint; ends the changes of one run
+const NAME_INDEX_FILE: &str = "name_index.bin";
+const NAME_INDEX_LOG: &str = "name_index.log";
+const MAGIC: &[u8; 8] = b"BOFNIDX2";
+const HEADER_LEN: usize = 32;
+/// Three characters of at most four bytes each.
+const GRAM_LEN: usize = 12;
+const GRAM_SLOT: usize = GRAM_LEN + 8 + 4;
+const ENTRY_SLOT: usize = 8 + 4;
+/// The log is merged into the index once it exceeds this share of the
+/// index's size, but not before it reaches `MIN_COMPACT_LEN`.
+const COMPACT_RATIO: u64 = 16;
+const MIN_COMPACT_LEN: u64 = 1 << 20;
+
+/// One indexed file as stored in the name index.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameIndexEntry {
+    pub hash: String,
+    pub root: String,
+    /// Stored path, as in files.json (`./sub/file`).
+    pub path: String,
+}
+
+impl NameIndexEntry {
+    /// The path below the root, without the leading `./`.
+    pub fn relative_path(&self) -> &str {
+        self.path.trim_start_matches("./")
+    }
+
+    pub fn name(&self) -> &str {
+        let path = self.relative_path();
+        path.rsplit('/').next().unwrap_or(path)
+    }
+
+    fn location(&self) -> (String, String) {
+        (self.root.clone(), self.path.clone())
+    }
+}
+
+/// Size and modification time (ns) of `path`, or `None` if it doesn't exist.
+pub fn source_fingerprint(path: &Path) -> io::Result<Option<(u64, u64)>> {
+    match fs::metadata(path) {
+        Ok(metadata) => {
+            let mtime = metadata
+                .modified()?
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_nanos() as u64)
+                .unwrap_or(0);
+            Ok(Some((metadata.len(), mtime)))
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// The distinct three-character sequences of `text`, lowercased.
+pub fn trigrams(text: &str) -> BTreeSet<String> {
+    let chars: Vec<char> = text.to_lowercase().chars().collect();
+    chars.windows(3).map(|w| w.iter().collect()).collect()
+}
+
+fn gram_key(gram: &str) -> [u8; GRAM_LEN] {
+    let mut key = [0; GRAM_LEN];
+    key[..gram.len()].copy_from_slice(gram.as_bytes());
+    key
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], pos: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(data.get(pos..pos + 8)?.try_into().ok()?))
+}
+
+fn encode_entry(entry: &NameIndexEntry) -> Vec<u8> {
+    [entry.hash.as_str(), entry.root.as_str(), entry.path.as_str()].join("\0").into_bytes()
+}
+
+fn decode_entry(data: &[u8]) -> Option<NameIndexEntry> {
+    let mut fields = std::str::from_utf8(data).ok()?.split('\0').map(str::to_string);
+    Some(NameIndexEntry {
+        hash: fields.next()?,
+        root: fields.next()?,
+        path: fields.next()?,
+    })
+}
+
+fn encode(source: (u64, u64), entries: &[NameIndexEntry]) -> Vec<u8> {
+    let mut grams: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for (id, entry) in entries.iter().enumerate() {
+        for gram in trigrams(entry.relative_path()) {
+            grams.entry(gram).or_default().push(id as u32);
+        }
+    }
+    let entries: Vec<Vec<u8>> = entries.iter().map(encode_entry).collect();
+
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&source.0.to_le_bytes());
+    out.extend_from_slice(&source.1.to_le_bytes());
+    out.extend_from_slice(&(entries.len() as u32).to_le_bytes());
+    out.extend_from_slice(&(grams.len() as u32).to_le_bytes());
+
+    let mut offset = (HEADER_LEN + grams.len() * GRAM_SLOT + entries.len() * ENTRY_SLOT) as u64;
+    for (gram, ids) in &grams {
+        out.extend_from_slice(&gram_key(gram));
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&(ids.len() as u32).to_le_bytes());
+        offset += ids.len() as u64 * 4;
+    }
+    for entry in &entries {
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&(entry.len() as u32).to_le_bytes());
+        offset += entry.len() as u64;
+    }
+    for ids in grams.values() {
+        ids.iter().for_each(|id| out.extend_from_slice(&id.to_le_bytes()));
+    }
+    for entry in entries {
+        out.extend_from_slice(&entry);
+    }
+    out
+}
+
+/// The entries of a whole index file; `None` if it isn't one or is damaged.
+fn decode(data: &[u8]) -> Option<Vec<NameIndexEntry>> {
+    if data.get(..MAGIC.len())? != MAGIC {
+        return None;
+    }
+    let entry_count = u32_at(data, 24)? as usize;
+    let gram_count = u32_at(data, 28)? as usize;
+
+    let entry_table = HEADER_LEN + gram_count * GRAM_SLOT;
+    let mut entries = Vec::with_capacity(entry_count);
+    for slot in data.get(entry_table..entry_table + entry_count * ENTRY_SLOT)?.chunks_exact(ENTRY_SLOT) {
+        let offset = u64_at(slot, 0)? as usize;
+        let len = u32_at(slot, 8)? as usize;
+        entries.push(decode_entry(data.get(offset..offset + len)?)?);
+    }
+    Some(entries)
+}
+
+/// The fingerprint in the header of an index file, if it is one.
+fn header_source(header: &[u8]) -> Option<(u64, u64)> {
+    if header.get(..MAGIC.len())? != MAGIC {
+        return None;
+    }
+    Some((u64_at(header, 8)?, u64_at(header, 16)?))
+}
+
+fn push_record(out: &mut Vec<u8>, tag: u8, payload: &[u8]) {
+    out.push(tag);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+}
+
+/// What the log changes about `name_index.bin`.
+#[derive(Default)]
+struct LogChanges {
+    /// Root and stored path -> the entry there now, or `None` once removed.
+    locations: HashMap<(String, String), Option<NameIndexEntry>>,
+    /// Roots whose entries in `name_index.bin` no longer count.
+    dropped_roots: HashSet<String>,
+    /// Fingerprint written by the last complete run.
+    source: Option<(u64, u64)>,
+    /// Whether the log ends in the partial changes of an interrupted run.
+    torn: bool,
+}
+
+impl LogChanges {
+    /// Replay a log. Only runs that got to write their fingerprint are applied.
+    fn parse(data: &[u8]) -> LogChanges {
+        let mut changes = LogChanges::default();
+        let mut pending: Vec<(u8, &[u8])> = Vec::new();
+        let mut pos = 0;
+        while pos < data.len() {
+            let (Some(&tag), Some(len)) = (data.get(pos), u32_at(data, pos + 1)) else {
+                break;
+            };
+            let Some(payload) = data.get(pos + 5..pos + 5 + len as usize) else {
+                break;
+            };
+            pos += 5 + len as usize;
+
+            if tag != b'=' {
+                pending.push((tag, payload));
+                continue;
+            }
+            let (Some(size), Some(mtime)) = (u64_at(payload, 0), u64_at(payload, 8)) else {
+                break;
+            };
+            for (tag, payload) in pending.drain(..) {
+                changes.apply(tag, payload);
+            }
+            changes.source = Some((size, mtime));
+        }
+        changes.torn = !pending.is_empty() || pos != data.len();
+        changes
+    }
+
+    fn apply(&mut self, tag: u8, payload: &[u8]) {
+        let text = String::from_utf8_lossy(payload);
+        match tag {
+            b'+' => {
+                if let Some(entry) = decode_entry(payload) {
+                    self.locations.insert(entry.location(), Some(entry));
+                }
+            }
+            b'-' => {
+                if let Some((root, path)) = text.split_once('\0') {
+                    self.locations.insert((root.to_string(), path.to_string()), None);
+                }
+            }
+            b'x' => {
+                self.locations.retain(|(root, _), _| *root != text);
+                self.dropped_roots.insert(text.to_string());
+            }
+            _ => {}
+        }
+    }
+
+    /// Whether an entry of `name_index.bin` still stands.
+    fn keeps(&self, entry: &NameIndexEntry) -> bool {
+        !self.dropped_roots.contains(&entry.root) && !self.locations.contains_key(&entry.location())
+    }
+
+    /// Entries added by the log.
+    fn added(&self) -> impl Iterator<Item = &NameIndexEntry> {
+        self.locations.values().flatten()
+    }
+}
+
+fn read_log(global_bof_dir: &Path) -> io::Result<(LogChanges, u64)> {
+    match fs::read(global_bof_dir.join(NAME_INDEX_LOG)) {
+        Ok(data) => Ok((LogChanges::parse(&data), data.len() as u64)),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok((LogChanges::default(), 0)),
+        Err(e) => Err(e),
+    }
+}
+
+/// Entries for the hashed files of `files`, stored under `root`.
+fn root_entries<'a>(root: &'a str, files: &'a [FileMetadata]) -> impl Iterator<Item = NameIndexEntry> + 'a {
+    files.iter().filter_map(move |file| {
+        Some(NameIndexEntry {
+            hash: file.hash.clone()?,
+            root: root.to_string(),
+            path: file.path.clone(),
+        })
+    })
+}
+
+/// Every hashed file in the files.json of `roots`.
+pub fn indexed_entries(roots: &BTreeSet<PathBuf>) -> io::Result<Vec<NameIndexEntry>> {
+    let mut entries = Vec::new();
+    for root in roots {
+        let (files, _) = load_metadata(&root.join(".bof"))?;
+        entries.extend(root_entries(&root.to_string_lossy(), &files));
+    }
+    Ok(entries)
+}
+
+fn write_index(global_bof_dir: &Path, source: (u64, u64), entries: &[NameIndexEntry]) -> io::Result<()> {
+    write_atomic(&global_bof_dir.join(NAME_INDEX_FILE), &encode(source, entries))?;
+    match fs::remove_file(global_bof_dir.join(NAME_INDEX_LOG)) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Bring the name index in line with the index run of `root`, whose files
+/// went from `previous` (stored path -> hash; `None` if it had none) to `files`. Called
+/// once the run's inverse table and files.json are written; `previous_source`
+/// is the inverse table's fingerprint before the run. Only this run's changes
+/// are appended to the log. If the index didn't describe the previous inverse
+/// table (e.g. after an interrupted run or `bof clear`), it is rebuilt from
+/// the files.json of every root in `roots`.
+pub fn update_name_index(
+    global_bof_dir: &Path,
+    roots: &BTreeSet<PathBuf>,
+    previous_source: Option<(u64, u64)>,
+    root: &str,
+    previous: Option<&HashMap<String, String>>,
+    files: &[FileMetadata],
+) -> io::Result<()> {
+    let source = source_fingerprint(&global_bof_dir.join("inverse_table.json"))?.unwrap_or_default();
+    let index_path = global_bof_dir.join(NAME_INDEX_FILE);
+
+    let mut header = [0; HEADER_LEN];
+    let (index_source, index_len) = match File::open(&index_path) {
+        Ok(mut file) => {
+            let len = file.metadata()?.len();
+            let source = file.read_exact(&mut header).ok().and_then(|_| header_source(&header));
+            (source, len)
+        }
+        Err(e) if e.kind() == ErrorKind::NotFound => (None, 0),
+        Err(e) => return Err(e),
+    };
+    let (changes, log_len) = read_log(global_bof_dir)?;
+    let current = index_source.is_some() && !changes.torn && changes.source.or(index_source) == previous_source;
+    if !current {
+        return write_index(global_bof_dir, source, &indexed_entries(roots)?);
+    }
+
+    let mut batch = Vec::new();
+    match previous {
+        Some(before) => {
+            let after: HashSet<&str> = files.iter().filter(|f| f.hash.is_some()).map(|f| f.path.as_str()).collect();
+            for path in before.keys().filter(|path| !after.contains(path.as_str())) {
+                push_record(&mut batch, b'-', format!("{}\0{}", root, path).as_bytes());
+            }
+            for entry in root_entries(root, files).filter(|e| before.get(&e.path) != Some(&e.hash)) {
+                push_record(&mut batch, b'+', &encode_entry(&entry));
+            }
+        }
+        None => {
+            // A root indexed afresh: whatever the index held for it is replaced.
+            push_record(&mut batch, b'x', root.as_bytes());
+            for entry in root_entries(root, files) {
+                push_record(&mut batch, b'+', &encode_entry(&entry));
+            }
+        }
+    }
+    append_batch(global_bof_dir, roots, batch, source, log_len, index_len, changes)
+}
+
+/// Append one run's changes to the log, or merge everything into a new index
+/// once the log would grow too large. A damaged index is rebuilt from the
+/// files.json of every root in `roots` instead.
+fn append_batch(
+    global_bof_dir: &Path,
+    roots: &BTreeSet<PathBuf>,
+    mut batch: Vec<u8>,
+    source: (u64, u64),
+    log_len: u64,
+    index_len: u64,
+    mut changes: LogChanges,
+) -> io::Result<()> {
+    let mut fingerprint = source.0.to_le_bytes().to_vec();
+    fingerprint.extend_from_slice(&source.1.to_le_bytes());
+    push_record(&mut batch, b'=', &fingerprint);
+
+    if log_len + batch.len() as u64 <= (index_len / COMPACT_RATIO).max(MIN_COMPACT_LEN) {
+        let mut log = OpenOptions::new().create(true).append(true).open(global_bof_dir.join(NAME_INDEX_LOG))?;
+        log.write_all(&batch)?;
+        return log.sync_data();
+    }
+
+    let data = fs::read(global_bof_dir.join(NAME_INDEX_FILE))?;
+    let Some(base) = decode(&data) else {
+        return write_index(global_bof_dir, source, &indexed_entries(roots)?);
+    };
+    let batch_changes = LogChanges::parse(&batch);
+    for root in batch_changes.dropped_roots {
+        changes.locations.retain(|(r, _), _| *r != root);
+        changes.dropped_roots.insert(root);
+    }
+    changes.locations.extend(batch_changes.locations);
+
+    let mut entries: Vec<NameIndexEntry> = base.into_iter().filter(|e| changes.keeps(e)).collect();
+    entries.extend(changes.added().cloned());
+    write_index(global_bof_dir, source, &entries)
+}
+
+/// Reads parts of the name index on demand, for searching.
+pub struct NameIndexReader {
+    file: File,
+    entry_count: usize,
+    gram_table: Vec<u8>,
+    changes: LogChanges,
+}
+
+impl NameIndexReader {
+    /// Open the name index, if it describes the current `inverse_table.json`. A
+    /// missing or stale index (e.g. after an interrupted run) is ignored.
+    pub fn open_current(global_bof_dir: &Path) -> io::Result<Option<NameIndexReader>> {
+        let Some(source) = source_fingerprint(&global_bof_dir.join("inverse_table.json"))? else {
+            return Ok(None);
+        };
+        let mut file = match File::open(global_bof_dir.join(NAME_INDEX_FILE)) {
+            Ok(file) => file,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        let mut header = [0; HEADER_LEN];
+        if file.read_exact(&mut header).is_err() {
+            return Ok(None);
+        }
+        let Some(index_source) = header_source(&header) else {
+            return Ok(None);
+        };
+        let (changes, _) = read_log(global_bof_dir)?;
+        if changes.torn || changes.source.unwrap_or(index_source) != source {
+            return Ok(None);
+        }
+        let entry_count = u32_at(&header, 24).unwrap_or_default() as usize;
+        let gram_count = u32_at(&header, 28).unwrap_or_default() as usize;
+
+        let mut gram_table = vec![0; gram_count * GRAM_SLOT];
+        if file.read_exact(&mut gram_table).is_err() {
+            return Ok(None);
+        }
+        Ok(Some(NameIndexReader { file, entry_count, gram_table, changes }))
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let mut data = vec![0; len];
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut data)?;
+        Ok(data)
+    }
+
+    fn ids(&mut self, gram: &str) -> io::Result<Vec<u32>> {
+        let key = gram_key(gram);
+        let (slots, _) = self.gram_table.as_chunks::<GRAM_SLOT>();
+        let Ok(found) = slots.binary_search_by(|slot| slot[..GRAM_LEN].cmp(&key[..])) else {
+            return Ok(Vec::new());
+        };
+        let offset = u64_at(&slots[found], GRAM_LEN).unwrap_or_default();
+        let count = u32_at(&slots[found], GRAM_LEN + 8).unwrap_or_default() as usize;
+
+        let data = self.read_at(offset, count * 4)?;
+        Ok(data.chunks_exact(4).filter_map(|id| u32_at(id, 0)).collect())
+    }
+
+    /// Ids of the entries whose paths contain every trigram of `literals`; all
+    /// entries when the literals are too short to narrow anything down.
+    fn candidate_ids(&mut self, literals: &[String]) -> io::Result<Vec<u32>> {
+        let grams: BTreeSet<String> = literals.iter().flat_map(|l| trigrams(l)).collect();
+        if grams.is_empty() {
+            return Ok((0..self.entry_count as u32).collect());
+        }
+
+        let mut lists = Vec::with_capacity(grams.len());
+        for gram in &grams {
+            let ids = self.ids(gram)?;
+            if ids.is_empty() {
+                return Ok(Vec::new());
+            }
+            lists.push(ids);
+        }
+        lists.sort_by_key(Vec::len);
+
+        let mut candidates = lists.swap_remove(0);
+        for ids in &lists {
+            candidates.retain(|id| ids.binary_search(id).is_ok());
+        }
+        Ok(candidates)
+    }
+
+    /// The entries that may contain all of `literals` (ignoring case) in their
+    /// path: those of `name_index.bin` sharing their trigrams, and every entry
+    /// added since by the log. `None` if the index turns out to be damaged; the
+    /// next index run that compacts it rebuilds it.
+    pub fn candidates(&mut self, literals: &[String]) -> io::Result<Option<Vec<NameIndexEntry>>> {
+        let mut entries = match self.candidate_ids(literals).and_then(|ids| self.entries(&ids)) {
+            Ok(entries) => entries,
+            Err(e) if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        entries.retain(|entry| self.changes.keeps(entry));
+        entries.extend(self.changes.added().cloned());
+        Ok(Some(entries))
+    }
+
+    /// The entries of `name_index.bin` with the given ids.
+    fn entries(&mut self, ids: &[u32]) -> io::Result<Vec<NameIndexEntry>> {
+        let entry_table = (HEADER_LEN + self.gram_table.len()) as u64;
+        let damaged = || io::Error::new(ErrorKind::InvalidData, "The name index is damaged");
+
+        // For a large share of the entries, one big read beats many small ones.
+        let bulk = if ids.len() * 16 > self.entry_count {
+            let mut rest = Vec::new();
+            self.file.seek(SeekFrom::Start(entry_table))?;
+            self.file.read_to_end(&mut rest)?;
+            Some(rest)
+        } else {
+            None
+        };
+
+        let mut entries = Vec::with_capacity(ids.len());
+        for &id in ids {
+            let slot_offset = entry_table + id as u64 * ENTRY_SLOT as u64;
+            let slot = match &bulk {
+                Some(rest) => {
+                    let start = (slot_offset - entry_table) as usize;
+                    rest.get(start..start + ENTRY_SLOT).ok_or_else(damaged)?.to_vec()
+                }
+                None => self.read_at(slot_offset, ENTRY_SLOT)?,
+            };
+            let offset = u64_at(&slot, 0).ok_or_else(damaged)?;
+            let len = u32_at(&slot, 8).ok_or_else(damaged)? as usize;
+
+            let data = match &bulk {
+                Some(rest) => {
+                    let start = (offset - entry_table) as usize;
+                    rest.get(start..start + len).ok_or_else(damaged)?.to_vec()
+                }
+                None => self.read_at(offset, len)?,
+            };
+            entries.push(decode_entry(&data).ok_or_else(damaged)?);
+        }
+        Ok(entries)
+    }
+}
+
+/// Text runs that every name matching the glob `pattern` contains.
+pub fn glob_literals(pattern: &str) -> Vec<String> {
+    let mut literals = Vec::new();
+    let mut current = String::new();
+    let mut chars = pattern.chars();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => current.extend(chars.next()),
+            '*' | '?' | '[' | '{' => {
+                literals.push(std::mem::take(&mut current));
+                // Classes and alternatives don't contribute a fixed text.
+                let close = match c {
+                    '[' => ']',
+                    '{' => '}',
+                    _ => continue,
+                };
+                let mut depth = 1;
+                for c in chars.by_ref() {
+                    if c == close {
+                        depth -= 1;
+                        if depth == 0 || close == ']' {
+                            break;
+                        }
+                    } else if c == '{' && close == '}' {
+                        depth += 1;
+                    }
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    literals.push(current);
+    literals.retain(|l| !l.is_empty());
+    literals
+}
+
+fn required_literals(hir: &Hir, literals: &mut Vec<String>) {
+    match hir.kind() {
+        HirKind::Literal(literal) => {
+            if let Ok(text) = std::str::from_utf8(&literal.0) {
+                literals.push(text.to_string());
+            }
+        }
+        HirKind::Capture(capture) => required_literals(&capture.sub, literals),
+        HirKind::Repetition(repetition) if repetition.min > 0 => required_literals(&repetition.sub, literals),
+        HirKind::Concat(parts) => parts.iter().for_each(|part| required_literals(part, literals)),
+        _ => {}
+    }
+}
+
+/// Text that every match of the regex `pattern` contains. The index ignores
+/// case, so the pattern is read case-sensitively here either way.
+pub fn regex_literals(pattern: &str) -> Vec<String> {
+    let mut literals = Vec::new();
+    if let Ok(hir) = regex_syntax::parse(pattern) {
+        required_literals(&hir, &mut literals);
+    }
+    literals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn entry(root: &str, path: &str, hash: &str) -> NameIndexEntry {
+        NameIndexEntry { hash: hash.to_string(), root: root.to_string(), path: path.to_string() }
+    }
+
+    fn file(path: &str, hash: &str) -> FileMetadata {
+        FileMetadata {
+            key: path.to_string(),
+            path: path.to_string(),
+            file_type: "file".to_string(),
+            ctime: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            size: 1,
+            hash: Some(hash.to_string()),
+            inode: None,
+            device: None,
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("bof-name-index-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn entries_know_their_name_and_relative_path() {
+        let entry = entry("/data", "./docs/report.pdf", "sha256:1");
+        assert_eq!(entry.relative_path(), "docs/report.pdf");
+        assert_eq!(entry.name(), "report.pdf");
+    }
+
+    #[test]
+    fn encode_and_decode_round_trip() {
+        let entries = vec![entry("/a", "./x/report.txt", "sha256:1"), entry("/b", "./ÄÖ ü.md", "sha256:2")];
+        let data = encode((12, 34), &entries);
+        assert_eq!(header_source(&data), Some((12, 34)));
+        assert_eq!(decode(&data), Some(entries));
+        assert_eq!(decode(b"BOFNIDX1"), None);
+        assert_eq!(decode(&data[..data.len() - 1]), None);
+    }
+
+    #[test]
+    fn log_replay_applies_complete_runs_only() {
+        let mut log = Vec::new();
+        push_record(&mut log, b'+', &encode_entry(&entry("/a", "./one.txt", "sha256:1")));
+        push_record(&mut log, b'-', b"/a\0./gone.txt");
+        push_record(&mut log, b'=', &[1; 16]);
+        let complete = log.len();
+        push_record(&mut log, b'x', b"/a");
+
+        let changes = LogChanges::parse(&log);
+        assert!(changes.torn);
+        assert_eq!(changes.source, Some((u64::from_le_bytes([1; 8]), u64::from_le_bytes([1; 8]))));
+        assert_eq!(changes.added().count(), 1);
+        assert!(!changes.keeps(&entry("/a", "./gone.txt", "sha256:9")));
+        assert!(changes.keeps(&entry("/a", "./other.txt", "sha256:9")));
+
+        let changes = LogChanges::parse(&log[..complete]);
+        assert!(!changes.torn);
+        push_record(&mut log, b'=', &[2; 16]);
+        let changes = LogChanges::parse(&log);
+        assert!(!changes.torn);
+        assert_eq!(changes.added().count(), 0);
+        assert!(!changes.keeps(&entry("/a", "./other.txt", "sha256:9")));
+        assert!(changes.keeps(&entry("/b", "./other.txt", "sha256:9")));
+    }
+
+    #[test]
+    fn updates_are_appended_and_read_back() {
+        let dir = temp_dir("update");
+        let global = dir.join("global");
+        let root = dir.join("root");
+        fs::create_dir_all(&global).unwrap();
+        fs::create_dir_all(root.join(".bof")).unwrap();
+        let roots = BTreeSet::from([root.clone()]);
+        let root_str = root.to_string_lossy().to_string();
+        let inverse_table = global.join("inverse_table.json");
+
+        let files = vec![file("./docs/report_2024.pdf", "sha256:1"), file("./src/main.rs", "sha256:2")];
+        fs::write(root.join(".bof/files.json"), serde_json::to_string(&files).unwrap()).unwrap();
+        fs::write(&inverse_table, "{}").unwrap();
+        update_name_index(&global, &roots, None, &root_str, None, &files).unwrap();
+        let base = fs::read(global.join(NAME_INDEX_FILE)).unwrap();
+        assert!(!global.join(NAME_INDEX_LOG).exists());
+
+        let mut reader = NameIndexReader::open_current(&global).unwrap().unwrap();
+        let found = reader.candidates(&["REPORT".to_string()]).unwrap().unwrap();
+        assert_eq!(found, vec![entry(&root_str, "./docs/report_2024.pdf", "sha256:1")]);
+
+        let previous_source = source_fingerprint(&inverse_table).unwrap();
+        let previous: HashMap<String, String> =
+            files.iter().map(|f| (f.path.clone(), f.hash.clone().unwrap())).collect();
+        let files = vec![file("./docs/report_2025.pdf", "sha256:1"), file("./src/main.rs", "sha256:3")];
+        fs::write(&inverse_table, "{\"files\": {}}").unwrap();
+        assert!(NameIndexReader::open_current(&global).unwrap().is_none(), "index is stale until updated");
+        update_name_index(&global, &roots, previous_source, &root_str, Some(&previous), &files).unwrap();
+
+        assert_eq!(fs::read(global.join(NAME_INDEX_FILE)).unwrap(), base, "the index file is left alone");
+        let mut reader = NameIndexReader::open_current(&global).unwrap().unwrap();
+        let mut found = reader.candidates(&["report_".to_string()]).unwrap().unwrap();
+        found.retain(|e| e.relative_path().contains("report_"));
+        assert_eq!(found, vec![entry(&root_str, "./docs/report_2025.pdf", "sha256:1")]);
+        let mut main = reader.candidates(&["main.rs".to_string()]).unwrap().unwrap();
+        main.retain(|e| e.name() == "main.rs");
+        assert_eq!(main, vec![entry(&root_str, "./src/main.rs", "sha256:3")], "the old version is gone");
+
+        // An interrupted append leaves the index unusable until it is rebuilt.
+        let mut log = OpenOptions::new().append(true).open(global.join(NAME_INDEX_LOG)).unwrap();
+        log.write_all(b"+\x05\x00\x00\x00ab").unwrap();
+        assert!(NameIndexReader::open_current(&global).unwrap().is_none());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_damaged_index_is_ignored_and_rebuilt() {
+        let dir = temp_dir("damaged");
+        let global = dir.join("global");
+        let root = dir.join("root");
+        fs::create_dir_all(&global).unwrap();
+        fs::create_dir_all(root.join(".bof")).unwrap();
+        let roots = BTreeSet::from([root.clone()]);
+        let root_str = root.to_string_lossy().to_string();
+        let inverse_table = global.join("inverse_table.json");
+
+        let files = vec![file("./docs/report.pdf", "sha256:1")];
+        fs::write(root.join(".bof/files.json"), serde_json::to_string(&files).unwrap()).unwrap();
+        fs::write(&inverse_table, "{}").unwrap();
+        update_name_index(&global, &roots, None, &root_str, None, &files).unwrap();
+
+        // Keep the header and gram table, losing the id lists and entries.
+        let reader = NameIndexReader::open_current(&global).unwrap().unwrap();
+        let data = fs::read(global.join(NAME_INDEX_FILE)).unwrap();
+        fs::write(global.join(NAME_INDEX_FILE), &data[..HEADER_LEN + reader.gram_table.len()]).unwrap();
+        let mut reader = NameIndexReader::open_current(&global).unwrap().unwrap();
+        assert!(reader.candidates(&["report".to_string()]).unwrap().is_none(), "search falls back to a full scan");
+
+        // Compaction rebuilds the index from files.json.
+        let source = source_fingerprint(&inverse_table).unwrap().unwrap();
+        let (changes, _) = read_log(&global).unwrap();
+        append_batch(&global, &roots, Vec::new(), source, u64::MAX / 2, 0, changes).unwrap();
+        let mut reader = NameIndexReader::open_current(&global).unwrap().unwrap();
+        let found = reader.candidates(&["report".to_string()]).unwrap().unwrap();
+        assert_eq!(found, vec![entry(&root_str, "./docs/report.pdf", "sha256:1")]);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn glob_literals_skip_wildcards_classes_and_alternatives() {
+        assert_eq!(glob_literals("report_*.pdf"), ["report_", ".pdf"]);
+        assert_eq!(glob_literals("img[0-9]?.{png,jpg}"), ["img", "."]);
+        assert_eq!(glob_literals(r"a\*b"), ["a*b"]);
+        assert!(glob_literals("*").is_empty());
+    }
+
+    #[test]
+    fn regex_literals_keep_only_required_text() {
+        assert_eq!(regex_literals(r"^report_\d{4}\.pdf$"), ["report_", ".pdf"]);
+        assert_eq!(regex_literals("(ab)+cd"), ["ab", "cd"]);
+        assert_eq!(regex_literals("draft|final"), Vec::<String>::new());
+        assert_eq!(regex_literals("x?yz"), ["yz"]);
+        assert!(regex_literals("(").is_empty());
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::HashMap;
+use std::env;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use crate::commands::email_config::load_email_config;
+use crate::commands::index::{load_metadata, location};
+use crate::commands::send_email::{send_email, Recipients, SelectedFile};
+use crate::commands::template::render_email;
+use crate::commands::webhook::{load_webhooks, post_json, sign};
+use crate::data_struct::{FileMetadata, WebhookConfig};
+
+/// Event sent for files picked on the command line, as `send-email` does.
+pub const FILE_EVENT: &str = "file.metadata";
+/// Event sent after `bof index` added, modified or removed files.
+pub const INDEX_EVENT: &str = "index.changed";
+
+/// A file an event is about, with where it lives and, for index events, how it changed.
+pub struct EventFile {
+    pub location: String,
+    pub change: Option<&'static str>,
+    pub meta: FileMetadata,
+}
+
+pub struct NotifyEvent {
+    pub event: &'static str,
+    pub root: Option<String>,
+    pub files: Vec<EventFile>,
+}
+
+impl NotifyEvent {
+    /// The JSON payload posted to webhooks.
+    pub fn to_json(&self) -> Value {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let files: Vec<Value> = self
+            .files
+            .iter()
+            .map(|file| {
+                let mut value = serde_json::to_value(&file.meta).unwrap_or_else(|_| json!({}));
+                value["location"] = json!(file.location);
+                if let Some(change) = file.change {
+                    value["change"] = json!(change);
+                }
+                value
+            })
+            .collect();
+
+        json!({
+            "event": self.event,
+            "timestamp": timestamp,
+            "root": self.root,
+            "files": files,
+        })
+    }
+}
+
+/// Somewhere events can be delivered to.
+pub trait Notifier {
+    /// Short description used in progress and error messages.
+    fn describe(&self) -> String;
+    fn notify(&self, event: &NotifyEvent) -> io::Result<()>;
+}
+
+/// Sends events through the email templates and an email profile.
+pub struct EmailNotifier {
+    pub profile: Option<String>,
+    pub recipients: Recipients,
+}
+
+impl Notifier for EmailNotifier {
+    fn describe(&self) -> String {
+        let to: Vec<String> = self.recipients.to.iter().map(|m| m.to_string()).collect();
+        format!("email to {}", to.join(", "))
+    }
+
+    fn notify(&self, event: &NotifyEvent) -> io::Result<()> {
+        let files: Vec<FileMetadata> = event.files.iter().map(|f| f.meta.clone()).collect();
+        let email = render_email(&files)?;
+        let config = load_email_config(self.profile.as_deref())?;
+        send_email(&config, self.profile.as_deref(), &self.recipients, &email, &[])
+    }
+}
+
+/// POSTs the JSON payload of events to a configured URL, signed with HMAC-SHA256
+/// when the webhook has a secret.
+pub struct WebhookNotifier {
+    pub name: String,
+    pub config: WebhookConfig,
+}
+
+impl Notifier for WebhookNotifier {
+    fn describe(&self) -> String {
+        format!("webhook '{}'", self.name)
+    }
+
+    fn notify(&self, event: &NotifyEvent) -> io::Result<()> {
+        let body = serde_json::to_vec(&event.to_json())?;
+        let mut headers = vec![("X-Bof-Event", event.event.to_string())];
+
+        if let Some(var) = &self.config.secret_env {
+            let secret = env::var(var).map_err(|_| {
+                io::Error::new(ErrorKind::NotFound, format!("Environment variable {} is not set", var))
+            })?;
+            headers.push(("X-Bof-Signature", sign(secret.as_bytes(), &body)));
+        }
+
+        post_json(&self.config.url, &body, &headers)?;
+        Ok(())
+    }
+}
+
+/// The webhooks called `names`, or every configured webhook when `names` is empty.
+pub fn webhook_notifiers(names: &[String]) -> io::Result<Vec<Box<dyn Notifier>>> {
+    let mut webhooks = load_webhooks()?.webhooks;
+    if names.is_empty() {
+        return Ok(webhooks
+            .into_iter()
+            .map(|(name, config)| Box::new(WebhookNotifier { name, config }) as Box<dyn Notifier>)
+            .collect());
+    }
+
+    names
+        .iter()
+        .map(|name| {
+            let config = webhooks
+                .remove(name)
+                .ok_or_else(|| io::Error::new(ErrorKind::NotFound, format!("No webhook named '{}'", name)))?;
+            Ok(Box::new(WebhookNotifier { name: name.clone(), config }) as Box<dyn Notifier>)
+        })
+        .collect()
+}
+
+/// The webhooks that asked to hear about `bof index` runs.
+pub fn index_notifiers() -> io::Result<Vec<Box<dyn Notifier>>> {
+    Ok(load_webhooks()?
+        .webhooks
+        .into_iter()
+        .filter(|(_, config)| config.on_index)
+        .map(|(name, config)| Box::new(WebhookNotifier { name, config }) as Box<dyn Notifier>)
+        .collect())
+}
+
+/// Deliver `event` to every notifier, reporting each outcome. One failing
+/// notifier doesn't stop the others.
+pub fn notify_all(notifiers: &[Box<dyn Notifier>], event: &NotifyEvent) {
+    for notifier in notifiers {
+        match notifier.notify(event) {
+            Ok(()) => println!("Notified {}", notifier.describe()),
+            Err(e) => eprintln!("Failed to notify {}: {}", notifier.describe(), e),
+        }
+    }
+}
+
+/// A `file.metadata` event for files picked with `--file`/`--search`.
+pub fn file_event(files: Vec<SelectedFile>) -> NotifyEvent {
+    NotifyEvent {
+        event: FILE_EVENT,
+        root: None,
+        files: files
+            .into_iter()
+            .map(|file| EventFile {
+                location: file.path.to_string_lossy().to_string(),
+                change: None,
+                meta: file.meta,
+            })
+            .collect(),
+    }
+}
+
+/// Compare `root`'s `files.json` with `before` (its contents ahead of an index
+/// run) and describe what was added, modified or removed, if anything.
+pub fn index_change_event(root: &Path, before: Vec<FileMetadata>) -> io::Result<Option<NotifyEvent>> {
+    let (after, _) = load_metadata(&root.join(".bof"))?;
+    let mut previous: HashMap<String, FileMetadata> = before.into_iter().map(|f| (f.path.clone(), f)).collect();
+    let mut files = Vec::new();
+
+    for meta in after {
+        let change = match previous.remove(&meta.path) {
+            None => "added",
+            Some(old) if old.hash != meta.hash || old.size != meta.size => "modified",
+            Some(_) => continue,
+        };
+        files.push(EventFile { location: location(root, &meta.path), change: Some(change), meta });
+    }
+    let mut removed: Vec<FileMetadata> = previous.into_values().collect();
+    removed.sort_by(|a, b| a.path.cmp(&b.path));
+    files.extend(removed.into_iter().map(|meta| EventFile {
+        location: location(root, &meta.path),
+        change: Some("removed"),
+        meta,
+    }));
+
+    if files.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(NotifyEvent {
+        event: INDEX_EVENT,
+        root: Some(root.to_string_lossy().to_string()),
+        files,
+    }))
+}
@@ -0,0 +1,213 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use lettre::address::Envelope;
+use lettre::{Address, Message, Transport};
+
+use crate::commands::email_config::load_email_config;
+use crate::commands::global::{get_global_bof_dir, write_atomic};
+use crate::commands::lock::lock_dir;
+use crate::commands::send_email::build_transport;
+use crate::data_struct::{OutboxMessage, OutboxStatus};
+
+/// Delay before the first retry; it doubles with every failed attempt.
+const BASE_RETRY_DELAY: u64 = 60;
+const MAX_RETRY_DELAY: u64 = 6 * 60 * 60;
+/// Attempts (including the original send) after which a message is marked failed.
+const MAX_ATTEMPTS: u32 = 8;
+
+/// Why `send_email` failed when the message was saved to the outbox instead,
+/// so callers can tell a delayed delivery from a lost one.
+#[derive(Debug)]
+pub struct QueuedError {
+    pub id: String,
+    pub cause: io::Error,
+}
+
+impl fmt::Display for QueuedError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (saved to the outbox as {}; retry with 'bof email flush')", self.cause, self.id)
+    }
+}
+
+impl std::error::Error for QueuedError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.cause)
+    }
+}
+
+/// The outbox id of the message `error` reports as queued, if it does.
+pub fn queued_id(error: &io::Error) -> Option<&str> {
+    error
+        .get_ref()
+        .and_then(|inner| inner.downcast_ref::<QueuedError>())
+        .map(|queued| queued.id.as_str())
+}
+
+pub fn get_outbox_dir() -> io::Result<PathBuf> {
+    let dir = get_global_bof_dir()?.join("outbox");
+    fs::create_dir_all(&dir)?;
+    Ok(dir)
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn retry_delay(attempts: u32) -> u64 {
+    let doublings = attempts.saturating_sub(1).min(16);
+    (BASE_RETRY_DELAY << doublings).min(MAX_RETRY_DELAY)
+}
+
+fn message_path(dir: &Path, id: &str) -> PathBuf {
+    dir.join(format!("{}.json", id))
+}
+
+fn save_message(dir: &Path, message: &OutboxMessage) -> io::Result<()> {
+    let json = serde_json::to_vec_pretty(message)?;
+    write_atomic(&message_path(dir, &message.id), &json)
+}
+
+/// Record a failed attempt: schedule the next retry, or give up after
+/// `MAX_ATTEMPTS`.
+fn record_failure(message: &mut OutboxMessage, error: &io::Error) {
+    message.attempts += 1;
+    message.last_error = Some(error.to_string());
+    message.next_attempt = now_secs() + retry_delay(message.attempts);
+    if message.attempts >= MAX_ATTEMPTS {
+        message.status = OutboxStatus::Failed;
+    }
+}
+
+/// Save a message whose delivery just failed so `bof email flush` can retry it,
+/// and return its outbox id.
+pub fn enqueue(profile: Option<&str>, subject: &str, message: &Message, error: &io::Error) -> io::Result<String> {
+    let dir = get_outbox_dir()?;
+    let created = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::ZERO);
+    let envelope = message.envelope();
+
+    let mut queued = OutboxMessage {
+        id: format!("{}-{:09}-{}", created.as_secs(), created.subsec_nanos(), std::process::id()),
+        profile: profile.map(str::to_string),
+        from: envelope.from().map(|a| a.to_string()).unwrap_or_default(),
+        recipients: envelope.to().iter().map(|a| a.to_string()).collect(),
+        subject: subject.to_string(),
+        created: created.as_secs(),
+        attempts: 0,
+        next_attempt: 0,
+        last_error: None,
+        status: OutboxStatus::Pending,
+        message: BASE64.encode(message.formatted()),
+    };
+    record_failure(&mut queued, error);
+    save_message(&dir, &queued)?;
+    Ok(queued.id)
+}
+
+/// Every message in the outbox, oldest first.
+pub fn load_outbox() -> io::Result<Vec<OutboxMessage>> {
+    let dir = get_outbox_dir()?;
+    let mut messages = Vec::new();
+
+    for entry in fs::read_dir(&dir)? {
+        let path = entry?.path();
+        if path.extension().is_none_or(|ext| ext != "json") {
+            continue;
+        }
+        let data = fs::read_to_string(&path)?;
+        let message: OutboxMessage = serde_json::from_str(&data).map_err(|e| {
+            io::Error::new(ErrorKind::InvalidData, format!("Could not parse {}: {}", path.display(), e))
+        })?;
+        messages.push(message);
+    }
+
+    messages.sort_by(|a, b| a.id.cmp(&b.id));
+    Ok(messages)
+}
+
+pub fn remove_message(id: &str) -> io::Result<()> {
+    let path = message_path(&get_outbox_dir()?, id);
+    if !path.exists() {
+        return Err(io::Error::new(ErrorKind::NotFound, format!("No outbox message with id '{}'", id)));
+    }
+    fs::remove_file(path)
+}
+
+fn parse_address(address: &str) -> io::Result<Address> {
+    address.parse::<Address>().map_err(|e| {
+        io::Error::new(ErrorKind::InvalidData, format!("Invalid email address '{}': {}", address, e))
+    })
+}
+
+fn deliver(message: &OutboxMessage) -> io::Result<()> {
+    let raw = BASE64
+        .decode(&message.message)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Corrupt outbox message: {e}")))?;
+    let from = if message.from.is_empty() { None } else { Some(parse_address(&message.from)?) };
+    let recipients = message
+        .recipients
+        .iter()
+        .map(|r| parse_address(r))
+        .collect::<io::Result<Vec<_>>>()?;
+    let envelope = Envelope::new(from, recipients)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Invalid envelope: {e}")))?;
+
+    let config = load_email_config(message.profile.as_deref())?;
+    build_transport(&config)?
+        .send_raw(&envelope, &raw)
+        .map_err(|e| io::Error::other(format!("SMTP send with {} failed: {}", config.server, e)))?;
+    Ok(())
+}
+
+/// Outcome of a `bof email flush` run.
+#[derive(Debug, Default)]
+pub struct FlushSummary {
+    pub sent: usize,
+    pub failed: usize,
+    pub waiting: usize,
+}
+
+/// Retry every pending message whose backoff has elapsed. With `all`, also
+/// retry messages that are not due yet or were given up on.
+pub fn flush_outbox(all: bool, lock_wait: Option<Duration>) -> io::Result<FlushSummary> {
+    let dir = get_outbox_dir()?;
+    // Keep two flushes (e.g. overlapping cron runs) from sending a message twice.
+    let _lock = lock_dir(&dir, lock_wait)?;
+    let now = now_secs();
+    let mut summary = FlushSummary::default();
+
+    for mut message in load_outbox()? {
+        let due = message.status == OutboxStatus::Pending && message.next_attempt <= now;
+        if !due && !all {
+            summary.waiting += 1;
+            continue;
+        }
+
+        match deliver(&message) {
+            Ok(()) => {
+                fs::remove_file(message_path(&dir, &message.id))?;
+                println!("Sent {} to {}", message.id, message.recipients.join(", "));
+                summary.sent += 1;
+            }
+            Err(e) => {
+                if all {
+                    message.status = OutboxStatus::Pending;
+                }
+                record_failure(&mut message, &e);
+                save_message(&dir, &message)?;
+                eprintln!("Failed to send {} (attempt {}): {}", message.id, message.attempts, e);
+                summary.failed += 1;
+            }
+        }
+    }
+
+    Ok(summary)
+}
@@ -0,0 +1,471 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, NaiveDate, TimeZone};
+
+use crate::commands::global::get_global_bof_dir;
+use crate::commands::index::{canonicalize_path, indexed_roots, load_inverse_table, load_metadata};
+use crate::commands::search::NameMatcher;
+use crate::commands::units::{parse_duration, parse_size};
+use crate::data_struct::FileMetadata;
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cmp {
+    Eq,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl Cmp {
+    fn test(self, left: u64, right: u64) -> bool {
+        match self {
+            Cmp::Eq => left == right,
+            Cmp::Lt => left < right,
+            Cmp::Le => left <= right,
+            Cmp::Gt => left > right,
+            Cmp::Ge => left >= right,
+        }
+    }
+
+    /// The comparison with its sides swapped, e.g. `<` becomes `>`.
+    fn flip(self) -> Cmp {
+        match self {
+            Cmp::Eq => Cmp::Eq,
+            Cmp::Lt => Cmp::Gt,
+            Cmp::Le => Cmp::Ge,
+            Cmp::Gt => Cmp::Lt,
+            Cmp::Ge => Cmp::Le,
+        }
+    }
+}
+
+/// A single `field:value` or `field<value` term.
+pub enum Condition {
+    Name(NameMatcher),
+    Path(NameMatcher),
+    Ext(String),
+    Type(String),
+    Hash(String),
+    Root(PathBuf),
+    Size(Cmp, u64),
+    /// Compares `mtime` (`ctime` for `Created`) with a Unix time.
+    Modified(Cmp, u64),
+    Created(Cmp, u64),
+    /// A Unix time range `[start, end)`, for `modified:2024-05-01`.
+    ModifiedOn(u64, u64),
+    CreatedOn(u64, u64),
+    /// Number of indexed roots holding the same content, from the global table.
+    Copies(Cmp, u64),
+}
+
+/// A parsed metadata query.
+pub enum Query {
+    Term(Condition),
+    Not(Box<Query>),
+    And(Box<Query>, Box<Query>),
+    Or(Box<Query>, Box<Query>),
+}
+
+/// An indexed file as seen by a query.
+pub struct QueryRecord {
+    pub location: PathBuf,
+    pub meta: FileMetadata,
+    pub copies: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    And,
+    Or,
+    Not,
+    Term(String),
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(ErrorKind::InvalidInput, message)
+}
+
+fn tokenize(text: &str) -> io::Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            _ => {
+                let mut word = String::new();
+                let mut quoted = false;
+                while let Some(&c) = chars.peek() {
+                    if c == '"' {
+                        quoted = !quoted;
+                    } else if !quoted && (c.is_whitespace() || c == '(' || c == ')') {
+                        break;
+                    } else {
+                        word.push(c);
+                    }
+                    chars.next();
+                }
+                if quoted {
+                    return Err(invalid(format!("Unclosed quote in query '{}'", text)));
+                }
+                tokens.push(match word.as_str() {
+                    "AND" | "and" => Token::And,
+                    "OR" | "or" => Token::Or,
+                    "NOT" | "not" => Token::Not,
+                    _ => Token::Term(word),
+                });
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+/// Recursive-descent parser; `OR` binds loosest, then `AND` (also implied
+/// between adjacent terms), then `NOT`.
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    now: u64,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn parse_or(&mut self) -> io::Result<Query> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Query::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> io::Result<Query> {
+        let mut left = self.parse_not()?;
+        loop {
+            match self.peek() {
+                Some(Token::And) => {
+                    self.next();
+                }
+                Some(Token::Term(_)) | Some(Token::Not) | Some(Token::Open) => {}
+                _ => return Ok(left),
+            }
+            left = Query::And(Box::new(left), Box::new(self.parse_not()?));
+        }
+    }
+
+    fn parse_not(&mut self) -> io::Result<Query> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Query::Not(Box::new(self.parse_not()?)));
+        }
+        self.parse_primary()
+    }
+
+    fn parse_primary(&mut self) -> io::Result<Query> {
+        match self.next() {
+            Some(Token::Open) => {
+                let inner = self.parse_or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(inner),
+                    _ => Err(invalid("Missing ')' in query".to_string())),
+                }
+            }
+            Some(Token::Term(term)) => Ok(Query::Term(parse_condition(&term, self.now)?)),
+            Some(Token::Close) => Err(invalid("Unexpected ')' in query".to_string())),
+            Some(other) => Err(invalid(format!("Expected a term, found {:?}", other))),
+            None => Err(invalid("Query ends too early".to_string())),
+        }
+    }
+}
+
+fn name_matcher(value: &str) -> io::Result<NameMatcher> {
+    if value.contains(['*', '?', '[', '{']) {
+        NameMatcher::glob(value, false)
+    } else {
+        Ok(NameMatcher::substring(value, false))
+    }
+}
+
+fn expand_home(value: &str) -> PathBuf {
+    match value.strip_prefix('~') {
+        Some(rest) => dirs::home_dir()
+            .map(|home| home.join(rest.trim_start_matches(['/', '\\'])))
+            .unwrap_or_else(|| PathBuf::from(value)),
+        None => PathBuf::from(value),
+    }
+}
+
+fn parse_date(value: &str) -> Option<u64> {
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()?;
+    let midnight = Local.from_local_datetime(&date.and_hms_opt(0, 0, 0)?).earliest()?;
+    u64::try_from(midnight.timestamp()).ok()
+}
+
+/// Parse the value of `modified`/`created`: an age such as `30d` (so `<30d`
+/// means within the last 30 days) or a `YYYY-MM-DD` date.
+fn parse_time_condition(field: &str, cmp: Cmp, value: &str, now: u64) -> io::Result<Condition> {
+    let modified = field == "modified";
+    if let Some(start) = parse_date(value) {
+        let end = start + DAY;
+        // The date stands for the whole day: `>` means after it, `<=` includes it.
+        let (cmp, time) = match cmp {
+            Cmp::Eq => {
+                return Ok(if modified { Condition::ModifiedOn(start, end) } else { Condition::CreatedOn(start, end) });
+            }
+            Cmp::Lt | Cmp::Ge => (cmp, start),
+            Cmp::Gt => (Cmp::Ge, end),
+            Cmp::Le => (Cmp::Lt, end),
+        };
+        return Ok(if modified { Condition::Modified(cmp, time) } else { Condition::Created(cmp, time) });
+    }
+
+    if cmp == Cmp::Eq {
+        return Err(invalid(format!("Use {}<AGE or {}>AGE with an age like '{}'", field, field, value)));
+    }
+    // A smaller age is a later time.
+    let threshold = now.saturating_sub(parse_duration(value)?);
+    Ok(if modified {
+        Condition::Modified(cmp.flip(), threshold)
+    } else {
+        Condition::Created(cmp.flip(), threshold)
+    })
+}
+
+fn parse_condition(term: &str, now: u64) -> io::Result<Condition> {
+    let field_end = term.find(|c: char| !(c.is_ascii_alphabetic() || c == '_')).unwrap_or(term.len());
+    let (field, rest) = term.split_at(field_end);
+
+    let (cmp, value) = if let Some(v) = rest.strip_prefix(">=") {
+        (Cmp::Ge, v)
+    } else if let Some(v) = rest.strip_prefix("<=") {
+        (Cmp::Le, v)
+    } else if let Some(v) = rest.strip_prefix('>') {
+        (Cmp::Gt, v)
+    } else if let Some(v) = rest.strip_prefix('<') {
+        (Cmp::Lt, v)
+    } else if let Some(v) = rest.strip_prefix(':').or_else(|| rest.strip_prefix('=')) {
+        (Cmp::Eq, v)
+    } else {
+        // A bare word matches names, like plain `bof search`.
+        return Ok(Condition::Name(name_matcher(term)?));
+    };
+
+    let field = field.to_ascii_lowercase();
+    let text_only = |condition: Condition| {
+        if cmp == Cmp::Eq {
+            Ok(condition)
+        } else {
+            Err(invalid(format!("'{}' only supports '{}:VALUE'", term, field)))
+        }
+    };
+
+    match field.as_str() {
+        "name" => text_only(Condition::Name(name_matcher(value)?)),
+        "path" => text_only(Condition::Path(name_matcher(value)?)),
+        "ext" => text_only(Condition::Ext(value.trim_start_matches('.').to_lowercase())),
+        "type" => text_only(Condition::Type(value.to_lowercase())),
+        "hash" => text_only(Condition::Hash(value.trim_start_matches("sha256:").to_lowercase())),
+        "root" => text_only(Condition::Root({
+            let path = expand_home(value);
+            canonicalize_path(&path).unwrap_or(path)
+        })),
+        "size" => Ok(Condition::Size(cmp, parse_size(value)?)),
+        "copies" => Ok(Condition::Copies(
+            cmp,
+            value.parse().map_err(|_| invalid(format!("Invalid number in '{}'", term)))?,
+        )),
+        "modified" | "mtime" => parse_time_condition("modified", cmp, value, now),
+        "created" | "ctime" => parse_time_condition("created", cmp, value, now),
+        _ => Err(invalid(format!(
+            "Unknown field '{}' (expected name, path, ext, type, hash, root, size, copies, modified or created)",
+            field
+        ))),
+    }
+}
+
+/// Parse a query such as `name:*.pdf size>10MB (modified<30d OR NOT type:file)`.
+pub fn parse_query(text: &str) -> io::Result<Query> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let mut parser = Parser { tokens: tokenize(text)?, pos: 0, now };
+
+    if parser.tokens.is_empty() {
+        return Err(invalid("Empty query".to_string()));
+    }
+    let query = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(query),
+        Some(token) => Err(invalid(format!("Unexpected {:?} in query", token))),
+    }
+}
+
+fn file_name(path: &str) -> &str {
+    Path::new(path).file_name().and_then(|n| n.to_str()).unwrap_or(path)
+}
+
+impl Condition {
+    fn matches(&self, record: &QueryRecord) -> bool {
+        let meta = &record.meta;
+        match self {
+            Condition::Name(matcher) => matcher.is_match(file_name(&meta.path)),
+            Condition::Path(matcher) => matcher.is_match(meta.path.trim_start_matches("./")),
+            Condition::Ext(ext) => Path::new(&meta.path)
+                .extension()
+                .is_some_and(|e| e.to_string_lossy().to_lowercase() == *ext),
+            Condition::Type(file_type) => meta.file_type == *file_type,
+            Condition::Hash(prefix) => meta
+                .hash
+                .as_deref()
+                .is_some_and(|h| h.trim_start_matches("sha256:").starts_with(prefix.as_str())),
+            Condition::Root(root) => record.location.starts_with(root),
+            Condition::Size(cmp, size) => cmp.test(meta.size, *size),
+            Condition::Modified(cmp, time) => cmp.test(meta.mtime, *time),
+            Condition::Created(cmp, time) => cmp.test(meta.ctime, *time),
+            Condition::ModifiedOn(start, end) => (*start..*end).contains(&meta.mtime),
+            Condition::CreatedOn(start, end) => (*start..*end).contains(&meta.ctime),
+            Condition::Copies(cmp, copies) => cmp.test(record.copies, *copies),
+        }
+    }
+}
+
+impl Query {
+    pub fn matches(&self, record: &QueryRecord) -> bool {
+        match self {
+            Query::Term(condition) => condition.matches(record),
+            Query::Not(inner) => !inner.matches(record),
+            Query::And(left, right) => left.matches(record) && right.matches(record),
+            Query::Or(left, right) => left.matches(record) || right.matches(record),
+        }
+    }
+}
+
+/// Every file in the `files.json` of each indexed root, with its copy count
+/// from the global inverse table.
+pub fn load_records() -> io::Result<Vec<QueryRecord>> {
+    let inverse_table = load_inverse_table(&get_global_bof_dir()?.join("inverse_table.json"))?;
+    let mut copies: HashMap<String, u64> = HashMap::new();
+    if let Some(files) = inverse_table["files"].as_object() {
+        for (key, entry) in files {
+            let count = entry["directories"].as_array().map_or(0, |d| d.len());
+            copies.insert(key.clone(), count as u64);
+        }
+    }
+
+    let mut records = Vec::new();
+    for root in indexed_roots()? {
+        let (files, _) = load_metadata(&root.join(".bof"))?;
+        for meta in files {
+            let copies = meta.hash.as_ref().and_then(|h| copies.get(h)).copied().unwrap_or(0);
+            records.push(QueryRecord {
+                location: root.join(meta.path.trim_start_matches("./")),
+                meta,
+                copies,
+            });
+        }
+    }
+    Ok(records)
+}
+
+/// The indexed files matching `query`, ordered by location.
+pub fn run_query(query: &Query) -> io::Result<Vec<QueryRecord>> {
+    let mut matches: Vec<QueryRecord> = load_records()?.into_iter().filter(|r| query.matches(r)).collect();
+    matches.sort_by(|a, b| a.location.cmp(&b.location));
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(path: &str, size: u64, mtime: u64) -> QueryRecord {
+        QueryRecord {
+            location: PathBuf::from("/data").join(path.trim_start_matches("./")),
+            meta: FileMetadata {
+                key: path.to_string(),
+                path: path.to_string(),
+                file_type: "file".to_string(),
+                ctime: mtime,
+                mtime,
+                mtime_nsec: 0,
+                size,
+                hash: Some("sha256:abc123".to_string()),
+                inode: None,
+                device: None,
+            },
+            copies: 1,
+        }
+    }
+
+    #[test]
+    fn adjacent_terms_are_anded_and_or_binds_loosest() {
+        let query = parse_query("name:*.pdf size>1KB OR ext:txt").unwrap();
+        let Query::Or(left, right) = query else {
+            panic!("expected OR at the top");
+        };
+        assert!(matches!(*left, Query::And(..)));
+        assert!(matches!(*right, Query::Term(Condition::Ext(ref ext)) if ext == "txt"));
+    }
+
+    #[test]
+    fn parses_comparisons_and_units() {
+        assert!(matches!(parse_query("size>=10MB").unwrap(), Query::Term(Condition::Size(Cmp::Ge, n)) if n == 10 << 20));
+        assert!(matches!(parse_query("copies>1").unwrap(), Query::Term(Condition::Copies(Cmp::Gt, 1))));
+        assert!(matches!(
+            parse_query("hash:sha256:ABC").unwrap(),
+            Query::Term(Condition::Hash(ref h)) if h == "abc"
+        ));
+        // A younger age is a later time, so `<30d` means after now - 30 days.
+        assert!(matches!(parse_query("modified<30d").unwrap(), Query::Term(Condition::Modified(Cmp::Gt, _))));
+        assert!(matches!(parse_query("created:2024-05-01").unwrap(), Query::Term(Condition::CreatedOn(start, end)) if end - start == DAY));
+    }
+
+    #[test]
+    fn rejects_malformed_queries() {
+        for text in ["", "(name:a", "name:a)", "NOT", "size:big", "ext>txt", "colour:red", "\"open", "modified:30d"] {
+            assert!(parse_query(text).is_err(), "{:?} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn evaluates_against_records() {
+        let query = parse_query("(name:report OR path:docs/*) NOT ext:tmp size<1KB").unwrap();
+        assert!(query.matches(&record("./docs/notes.txt", 10, 0)));
+        assert!(query.matches(&record("./report_2024.pdf", 10, 0)));
+        assert!(!query.matches(&record("./report_2024.tmp", 10, 0)));
+        assert!(!query.matches(&record("./report_2024.pdf", 4096, 0)));
+        assert!(!query.matches(&record("./other.txt", 10, 0)));
+    }
+
+    #[test]
+    fn quoted_values_keep_spaces() {
+        let query = parse_query("name:\"annual report\"").unwrap();
+        assert!(query.matches(&record("./Annual Report.pdf", 1, 0)));
+        assert!(!query.matches(&record("./annual.pdf", 1, 0)));
+    }
+}
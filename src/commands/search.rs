@@ -1,57 +1,301 @@
-use std::fs;
+use std::collections::{BTreeMap, BTreeSet};
 use std::io;
-use std::io::empty;
-use std::path::PathBuf;
-use serde_json::{Value, json};
+use globset::{GlobBuilder, GlobMatcher};
+use regex_automata::meta::Regex;
+use regex_automata::util::syntax;
 
 use crate::commands::global::get_global_bof_dir;
+use crate::commands::index::indexed_roots;
+use crate::commands::name_index::{glob_literals, indexed_entries, regex_literals, NameIndexReader};
 
 #[derive(Debug)]
 pub struct SearchResult {
+    /// The matched name, or the path below the root with `SearchTarget::Path`.
     pub file_name: String,
     pub directories: Vec<String>,
+    /// How well the name matched in fuzzy mode; higher is better.
+    pub score: Option<i64>,
 }
 
+/// What `bof search` matches its pattern against.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchTarget {
+    /// The file name.
+    Name,
+    /// The path below the indexed root, such as `src/main.rs`.
+    Path,
+}
+
+/// How `bof search` compares a pattern with stored file names.
+pub enum NameMatcher {
+    /// The name contains the pattern.
+    Substring { pattern: String, case_sensitive: bool },
+    /// The whole name matches a shell glob such as `*.tar.gz`.
+    Glob(GlobMatcher),
+    /// The regular expression matches somewhere in the name; anchor it with `^`/`$`.
+    /// `literals` is the text any match must contain, used to narrow the search.
+    Regex { regex: Regex, literals: Vec<String> },
+    /// The pattern's characters appear in order in the name, or nearly do
+    /// (a typo or two); see `fuzzy_score`.
+    Fuzzy { pattern: Vec<char>, case_sensitive: bool },
+}
+
+impl NameMatcher {
+    pub fn substring(pattern: &str, case_sensitive: bool) -> NameMatcher {
+        let pattern = if case_sensitive { pattern.to_string() } else { pattern.to_lowercase() };
+        NameMatcher::Substring { pattern, case_sensitive }
+    }
+
+    pub fn glob(pattern: &str, case_sensitive: bool) -> io::Result<NameMatcher> {
+        let glob = GlobBuilder::new(pattern)
+            .case_insensitive(!case_sensitive)
+            .build()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid glob '{}': {}", pattern, e)))?;
+        Ok(NameMatcher::Glob(glob.compile_matcher()))
+    }
+
+    pub fn regex(pattern: &str, case_sensitive: bool) -> io::Result<NameMatcher> {
+        let regex = Regex::builder()
+            .syntax(syntax::Config::new().case_insensitive(!case_sensitive))
+            .build(pattern)
+            .map_err(|e| {
+                let reason = e.syntax_error().map(|s| s.to_string()).unwrap_or_else(|| e.to_string());
+                io::Error::new(io::ErrorKind::InvalidInput, format!("Invalid regex '{}': {}", pattern, reason))
+            })?;
+        Ok(NameMatcher::Regex { regex, literals: regex_literals(pattern) })
+    }
+
+    pub fn fuzzy(pattern: &str, case_sensitive: bool) -> NameMatcher {
+        let pattern = if case_sensitive { pattern.to_string() } else { pattern.to_lowercase() };
+        NameMatcher::Fuzzy { pattern: pattern.chars().collect(), case_sensitive }
+    }
+
+    /// The fuzzy score of `name`, or `None` if it doesn't match. Other modes
+    /// don't rank, so any match scores `Some(0)`.
+    pub fn score(&self, name: &str) -> Option<i64> {
+        match self {
+            NameMatcher::Fuzzy { pattern, case_sensitive } => {
+                let name: Vec<char> = if *case_sensitive {
+                    name.chars().collect()
+                } else {
+                    name.to_lowercase().chars().collect()
+                };
+                fuzzy_score(pattern, &name)
+            }
+            _ if self.is_match(name) => Some(0),
+            _ => None,
+        }
+    }
+
+    pub fn is_match(&self, name: &str) -> bool {
+        match self {
+            NameMatcher::Fuzzy { .. } => self.score(name).is_some(),
+            NameMatcher::Substring { pattern, case_sensitive: true } => name.contains(pattern.as_str()),
+            NameMatcher::Substring { pattern, case_sensitive: false } => name.to_lowercase().contains(pattern.as_str()),
+            NameMatcher::Glob(glob) => glob.is_match(name),
+            NameMatcher::Regex { regex, .. } => regex.is_match(name),
+        }
+    }
+
+    /// Text every matching name contains, ignoring case. Fuzzy matches tolerate
+    /// typos, so they require nothing.
+    pub fn required_literals(&self) -> Vec<String> {
+        match self {
+            NameMatcher::Substring { pattern, .. } => vec![pattern.clone()],
+            NameMatcher::Glob(glob) => glob_literals(glob.glob().glob()),
+            NameMatcher::Regex { literals, .. } => literals.clone(),
+            NameMatcher::Fuzzy { .. } => Vec::new(),
+        }
+    }
+}
+
+const MATCH_SCORE: i64 = 16;
+/// Extra score for a match right after the previous one.
+const CONSECUTIVE_BONUS: i64 = 12;
+/// Extra score for a match at the start of the name or of a word in it.
+const BOUNDARY_BONUS: i64 = 8;
+const GAP_PENALTY: i64 = 3;
+
+fn is_boundary(name: &[char], j: usize) -> bool {
+    j == 0 || matches!(name[j - 1], '_' | '-' | '.' | ' ' | '/')
+}
+
+/// Best score of `pattern` as a subsequence of `name`: every matched character
+/// scores, more so when consecutive or at a word start, and skipped characters
+/// between matches cost a little.
+fn subsequence_score(pattern: &[char], name: &[char]) -> Option<i64> {
+    const NONE: i64 = i64::MIN;
+    // best[j]: best score with the current pattern character matched at name[j].
+    let mut best: Vec<i64> = vec![NONE; name.len()];
+
+    for (i, &pc) in pattern.iter().enumerate() {
+        let mut next = vec![NONE; name.len()];
+        // Best score of the previous character matched at some k < j, minus
+        // GAP_PENALTY for each of the j - k - 1 characters skipped since.
+        let mut carried = NONE;
+        for j in 0..name.len() {
+            if j > 0 {
+                let decayed = if carried == NONE { NONE } else { carried - GAP_PENALTY };
+                carried = decayed.max(best[j - 1]);
+            }
+            if name[j] != pc {
+                continue;
+            }
+
+            let bonus = MATCH_SCORE + if is_boundary(name, j) { BOUNDARY_BONUS } else { 0 };
+            next[j] = if i == 0 {
+                bonus
+            } else {
+                let consecutive = if j > 0 && best[j - 1] != NONE { best[j - 1] + CONSECUTIVE_BONUS } else { NONE };
+                match consecutive.max(carried) {
+                    NONE => NONE,
+                    previous => previous + bonus,
+                }
+            };
+        }
+        best = next;
+    }
+
+    best.into_iter().filter(|&s| s != NONE).max()
+}
+
+/// Smallest edit distance between `pattern` and any part of `name`.
+fn substring_distance(pattern: &[char], name: &[char]) -> usize {
+    // Row for the empty pattern is all zeros: a match may start anywhere.
+    let mut previous = vec![0; name.len() + 1];
+    for (i, &pc) in pattern.iter().enumerate() {
+        let mut current = vec![i + 1; name.len() + 1];
+        for j in 1..=name.len() {
+            let substitute = previous[j - 1] + usize::from(name[j - 1] != pc);
+            current[j] = substitute.min(previous[j] + 1).min(current[j - 1] + 1);
+        }
+        previous = current;
+    }
+    previous.into_iter().min().unwrap_or(pattern.len())
+}
+
+/// Score `name` against `pattern`. Subsequence matches (`rprt` in `report`)
+/// always outrank near misses, which are accepted with up to one typo per four
+/// pattern characters (`reprot`).
+fn fuzzy_score(pattern: &[char], name: &[char]) -> Option<i64> {
+    if pattern.is_empty() {
+        return Some(0);
+    }
+    if let Some(score) = subsequence_score(pattern, name) {
+        // Prefer shorter names among equally good matches.
+        return Some(score - name.len() as i64 / 8);
+    }
+
+    let distance = substring_distance(pattern, name);
+    let allowed = pattern.len() / 4 + 1;
+    if distance <= allowed && distance < pattern.len() {
+        Some(-(distance as i64) * MATCH_SCORE - name.len() as i64 / 8)
+    } else {
+        None
+    }
+}
+
+/// Case-insensitive substring search, as plain `bof search` does.
 pub fn search_by_name(pattern: &str) -> io::Result<Vec<SearchResult>> {
+    search(&NameMatcher::substring(pattern, false), SearchTarget::Name)
+}
+
+/// Match `matcher` against the stored files' names or paths. With an
+/// up-to-date name index only files sharing its trigrams are checked;
+/// otherwise the files.json of every indexed root is scanned.
+pub fn search(matcher: &NameMatcher, target: SearchTarget) -> io::Result<Vec<SearchResult>> {
     let global_bof_dir = get_global_bof_dir()?;
-    let inverse_table_path = global_bof_dir.join("inverse_table.json");
-
-    if !inverse_table_path.exists() {
-        return Ok(Vec::new());
-    }
-
-    let data = fs::read_to_string(&inverse_table_path)?;
-    let inverse_json: Value = serde_json::from_str(&data)
-        .unwrap_or_else(|_| json!({ "files": {} }));
-
-    let mut results = Vec::new();
-    if let Some(files_obj) = inverse_json.get("files").and_then(|f| f.as_object()) {
-        for (_file_key, file_info) in files_obj.iter() {
-            let empty_array = Vec::new();
-            let file_name = file_info.get("name")
-                .and_then(|n| n.as_str())
-                .unwrap_or("")
-                .to_string();
-
-            if file_name.to_lowercase().contains(&pattern.to_lowercase()) {
-                let dirs_array = file_info.get("directories")
-                    .and_then(|d| d.as_array())
-                    .unwrap_or(&empty_array);
-                
-                let mut directories = Vec::new();
-                for d in dirs_array {
-                    if let Some(dir_str) = d.as_str() {
-                        directories.push(dir_str.to_string());
-                    }
-                }
+    let fuzzy = matches!(matcher, NameMatcher::Fuzzy { .. });
 
-                results.push(SearchResult {
-                    file_name,
-                    directories,
-                });
-            }
+    let indexed = match NameIndexReader::open_current(&global_bof_dir)? {
+        Some(mut index) => index.candidates(&matcher.required_literals())?,
+        None => None,
+    };
+    let entries = match indexed {
+        Some(entries) => entries,
+        None => indexed_entries(&indexed_roots()?)?,
+    };
+
+    // One result per name (or path) a file is known by, listing the roots
+    // holding it there.
+    let mut matches: BTreeMap<(String, String), (i64, BTreeSet<String>)> = BTreeMap::new();
+    for entry in entries {
+        let text = match target {
+            SearchTarget::Name => entry.name(),
+            SearchTarget::Path => entry.relative_path(),
+        };
+        if let Some((_, roots)) = matches.get_mut(&(entry.hash.clone(), text.to_string())) {
+            roots.insert(entry.root);
+        } else if let Some(score) = matcher.score(text) {
+            let key = (entry.hash.clone(), text.to_string());
+            matches.insert(key, (score, BTreeSet::from([entry.root])));
         }
     }
 
+    let mut results: Vec<SearchResult> = matches
+        .into_iter()
+        .map(|((_, file_name), (score, roots))| SearchResult {
+            file_name,
+            directories: roots.into_iter().collect(),
+            score: fuzzy.then_some(score),
+        })
+        .collect();
+    sort_results(&mut results);
     Ok(results)
 }
+
+/// Best fuzzy score first; names break ties and order the other modes.
+fn sort_results(results: &mut [SearchResult]) {
+    results.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.file_name.cmp(&b.file_name)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn score(pattern: &str, name: &str) -> Option<i64> {
+        let pattern: Vec<char> = pattern.chars().collect();
+        let name: Vec<char> = name.chars().collect();
+        fuzzy_score(&pattern, &name)
+    }
+
+    #[test]
+    fn fuzzy_matches_subsequences() {
+        assert!(score("rprt", "report_final.docx").is_some());
+        assert!(score("", "anything").is_some());
+        assert!(score("xyz", "report").is_none());
+    }
+
+    #[test]
+    fn fuzzy_prefers_consecutive_and_word_start_matches() {
+        assert!(score("rep", "report") > score("rep", "rxexp"));
+        assert!(score("fin", "report_final") > score("fin", "refining"));
+        // Shorter names win among otherwise equal matches.
+        assert!(score("report", "report.txt") > score("report", "report_with_a_long_tail.txt"));
+    }
+
+    #[test]
+    fn fuzzy_tolerates_a_typo_below_any_subsequence_match() {
+        let typo = score("reprot", "report").expect("one transposition is close enough");
+        let subsequence = score("rpt", "report").unwrap();
+        assert!(typo < subsequence);
+        assert!(score("abcd", "wxyz").is_none());
+    }
+
+    #[test]
+    fn matchers_compare_names() {
+        assert!(NameMatcher::substring("REP", false).is_match("my_report.txt"));
+        assert!(!NameMatcher::substring("REP", true).is_match("my_report.txt"));
+        assert!(NameMatcher::glob("*.tar.gz", false).unwrap().is_match("backup.TAR.gz"));
+        assert!(!NameMatcher::glob("*.tar.gz", true).unwrap().is_match("backup.TAR.gz"));
+        assert!(NameMatcher::regex(r"^report_\d{4}", false).unwrap().is_match("Report_2024.pdf"));
+        assert!(NameMatcher::regex("(", false).is_err());
+    }
+
+    #[test]
+    fn required_literals_narrow_the_index_lookup() {
+        assert_eq!(NameMatcher::substring("Report", false).required_literals(), ["report"]);
+        assert_eq!(NameMatcher::glob("report_*.pdf", false).unwrap().required_literals(), ["report_", ".pdf"]);
+        assert!(NameMatcher::fuzzy("rprt", false).required_literals().is_empty());
+    }
+}
@@ -0,0 +1,142 @@
+use std::env;
+use std::io::{self, ErrorKind};
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use sha2::Sha256;
+
+use crate::data_struct::EncryptedSecret;
+
+/// Environment variable checked for the passphrase before prompting on the terminal.
+pub const PASSPHRASE_ENV: &str = "BOF_EMAIL_PASSPHRASE";
+
+const KDF_ITERATIONS: u32 = 600_000;
+const SALT_LEN: usize = 16;
+
+fn derive_key(passphrase: &str, salt: &[u8], iterations: u32) -> Key {
+    let mut key = Key::default();
+    pbkdf2::pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, iterations, &mut key);
+    key
+}
+
+fn decode(field: &str, value: &str) -> io::Result<Vec<u8>> {
+    BASE64
+        .decode(value)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("Corrupt encrypted {field}: {e}")))
+}
+
+/// Encrypt `secret` with ChaCha20-Poly1305 under a key derived from `passphrase`.
+pub fn encrypt_secret(secret: &str, passphrase: &str) -> io::Result<EncryptedSecret> {
+    encrypt_with_iterations(secret, passphrase, KDF_ITERATIONS)
+}
+
+fn encrypt_with_iterations(secret: &str, passphrase: &str, iterations: u32) -> io::Result<EncryptedSecret> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt, iterations));
+    let ciphertext = cipher
+        .encrypt(&nonce, secret.as_bytes())
+        .map_err(|_| io::Error::other("Encryption failed"))?;
+
+    Ok(EncryptedSecret {
+        iterations,
+        salt: BASE64.encode(salt),
+        nonce: BASE64.encode(nonce),
+        ciphertext: BASE64.encode(ciphertext),
+    })
+}
+
+pub fn decrypt_secret(secret: &EncryptedSecret, passphrase: &str) -> io::Result<String> {
+    let salt = decode("salt", &secret.salt)?;
+    let nonce = decode("nonce", &secret.nonce)?;
+    let ciphertext = decode("ciphertext", &secret.ciphertext)?;
+    if nonce.len() != 12 {
+        return Err(io::Error::new(ErrorKind::InvalidData, "Corrupt encrypted nonce"));
+    }
+
+    let cipher = ChaCha20Poly1305::new(&derive_key(passphrase, &salt, secret.iterations));
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| io::Error::new(ErrorKind::PermissionDenied, "Wrong passphrase for the stored email password"))?;
+
+    String::from_utf8(plaintext)
+        .map_err(|_| io::Error::new(ErrorKind::InvalidData, "Stored email password is not valid UTF-8"))
+}
+
+/// Passphrase from `BOF_EMAIL_PASSPHRASE`, or typed on the terminal. When
+/// `confirm` is set the user has to type it twice.
+pub fn read_passphrase(confirm: bool) -> io::Result<String> {
+    if let Ok(passphrase) = env::var(PASSPHRASE_ENV) {
+        if passphrase.is_empty() {
+            return Err(io::Error::new(ErrorKind::InvalidInput, format!("{} must not be empty", PASSPHRASE_ENV)));
+        }
+        return Ok(passphrase);
+    }
+
+    let passphrase = rpassword::prompt_password("Passphrase for the stored email password: ")?;
+    if confirm {
+        let again = rpassword::prompt_password("Repeat passphrase: ")?;
+        if again != passphrase {
+            return Err(io::Error::new(ErrorKind::InvalidInput, "Passphrases do not match"));
+        }
+    }
+    if passphrase.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "Passphrase must not be empty"));
+    }
+    Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Few iterations keep the tests fast; decryption reads the count from the secret.
+    const TEST_ITERATIONS: u32 = 1_000;
+
+    fn encrypt(secret: &str, passphrase: &str) -> EncryptedSecret {
+        encrypt_with_iterations(secret, passphrase, TEST_ITERATIONS).unwrap()
+    }
+
+    #[test]
+    fn encrypted_secret_round_trips() {
+        let encrypted = encrypt("hunter2", "correct horse");
+        assert_eq!(encrypted.iterations, TEST_ITERATIONS);
+        assert_ne!(encrypted.ciphertext, BASE64.encode("hunter2"));
+        assert_eq!(decrypt_secret(&encrypted, "correct horse").unwrap(), "hunter2");
+    }
+
+    #[test]
+    fn wrong_passphrase_is_rejected() {
+        let encrypted = encrypt("hunter2", "correct horse");
+        let err = decrypt_secret(&encrypted, "battery staple").unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    }
+
+    #[test]
+    fn each_encryption_uses_a_fresh_salt_and_nonce() {
+        let first = encrypt("hunter2", "pass");
+        let second = encrypt("hunter2", "pass");
+        assert_ne!(first.salt, second.salt);
+        assert_ne!(first.nonce, second.nonce);
+    }
+
+    #[test]
+    fn corrupt_fields_are_reported() {
+        let mut encrypted = encrypt("hunter2", "pass");
+        encrypted.nonce = BASE64.encode([0u8; 4]);
+        assert_eq!(decrypt_secret(&encrypted, "pass").unwrap_err().kind(), ErrorKind::InvalidData);
+        encrypted.salt = "not base64!".to_string();
+        assert_eq!(decrypt_secret(&encrypted, "pass").unwrap_err().kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn empty_passphrase_variable_is_rejected() {
+        env::set_var(PASSPHRASE_ENV, "");
+        assert_eq!(read_passphrase(false).unwrap_err().kind(), ErrorKind::InvalidInput);
+        env::remove_var(PASSPHRASE_ENV);
+    }
+}
@@ -1,166 +1,284 @@
+use std::collections::{BTreeSet, HashSet};
 use std::fs;
 use std::io::{self, ErrorKind};
 use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use serde_json;
+use lettre::message::{header::ContentType, Attachment, Mailbox, MultiPart, SinglePart};
+use lettre::transport::smtp::{self, authentication::Credentials};
+use lettre::{Message, SmtpTransport, Transport};
 
-use crate::data_struct::{FileMetadata, EmailConfig};
-use crate::commands::global::get_global_bof_dir;
-use crate::commands::index::canonicalize_path; 
-use std::process::Command;
+use crate::data_struct::{EmailConfig, FileMetadata, SmtpSecurity};
+use crate::commands::email_config::load_email_config;
+use crate::commands::attachment::{
+    check_attachment_size, file_attachment, manifest_attachment, zip_attachments, EmailAttachment, ManifestFormat,
+};
+use crate::commands::find_file::{find_bof_dir_for_path, find_indexed_file};
+use crate::commands::index::{canonicalize_path, load_metadata};
+use crate::commands::outbox;
+use crate::commands::search::search_by_name;
+use crate::commands::template::{render_email, RenderedEmail};
 
-pub fn send_file_metadata_email(file_path_str: &str, recipient: &str) -> io::Result<()> {
-    let abs_file = canonicalize_path(Path::new(file_path_str))?;
+pub const SMTP_TIMEOUT: Duration = Duration::from_secs(30);
 
-    let bof_dir = find_bof_dir_for_path(&abs_file).ok_or_else(|| {
-        io::Error::new(
-            ErrorKind::NotFound,
-            format!("No .bof folder found for file: {}", abs_file.display()),
-        )
-    })?;
-
-    let files_json = bof_dir.join("files.json");
-    if !files_json.exists() {
-        return Err(io::Error::new(
-            ErrorKind::NotFound,
-            format!("{} not found; did you run 'bof index'?", files_json.display()),
-        ));
-    }
-
-    let data = fs::read_to_string(&files_json)?;
-    let all_files: Vec<FileMetadata> = serde_json::from_str(&data)
-        .map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("JSON parse error: {e}")))?;
-
-    let maybe_meta = all_files.iter().find(|m| {
-        let file_in_index = bof_dir
-            .parent()
-            .unwrap_or_else(|| Path::new("."))
-            .join(&m.path);
-
-        if let Ok(index_abs) = canonicalize_path(&file_in_index) {
-            index_abs == abs_file
-        } else {
-            false
-        }
-    });
-
-    let file_meta = match maybe_meta {
-        Some(m) => m,
-        None => {
-            return Err(io::Error::new(
-                ErrorKind::NotFound,
-                format!(
-                    "File '{}' not found in {}",
-                    abs_file.display(),
-                    files_json.display()
-                ),
-            ));
-        }
+/// What to attach to a `send-email` message besides the metadata in its body.
+#[derive(Debug, Clone)]
+pub struct AttachOptions {
+    pub attach_files: bool,
+    pub zip: bool,
+    pub manifest: Option<ManifestFormat>,
+    pub max_size: u64,
+}
+
+/// The `To`, `Cc` and `Bcc` mailboxes of a message.
+#[derive(Debug, Clone, Default)]
+pub struct Recipients {
+    pub to: Vec<Mailbox>,
+    pub cc: Vec<Mailbox>,
+    pub bcc: Vec<Mailbox>,
+}
+
+fn parse_mailbox(address: &str) -> io::Result<Mailbox> {
+    address.parse::<Mailbox>().map_err(|e| {
+        io::Error::new(ErrorKind::InvalidInput, format!("Invalid email address '{}': {}", address, e))
+    })
+}
+
+/// Parse every address up front so a typo fails before any file is read or
+/// any connection is opened.
+pub fn parse_recipients(to: &[String], cc: &[String], bcc: &[String]) -> io::Result<Recipients> {
+    let parse_all = |addresses: &[String]| addresses.iter().map(|a| parse_mailbox(a)).collect::<io::Result<Vec<_>>>();
+
+    let recipients = Recipients {
+        to: parse_all(to)?,
+        cc: parse_all(cc)?,
+        bcc: parse_all(bcc)?,
     };
+    if recipients.to.is_empty() {
+        return Err(io::Error::new(ErrorKind::InvalidInput, "At least one --to recipient is required"));
+    }
+    Ok(recipients)
+}
 
-    let email_config = load_email_config()?;
-
-    let body = format!(
-        "Hello,\n\nHere is the metadata for the file you requested:\n\n\
-         Path: {}\n\
-         Type: {}\n\
-         Size: {} bytes\n\
-         Created: {}\n\
-         Modified: {}\n\n\
-         Sent from Rust.\n\
-         Enjoy!\n",
-        file_meta.path,
-        file_meta.file_type,
-        file_meta.size,
-        file_meta.ctime,
-        file_meta.mtime
-    );
-
-    send_email_via_python(
-        &email_config.address,   // outlook_username
-        &email_config.password,  // outlook_password
-        &email_config.address,   // from_address
-        recipient,               // to_address
-        &body,
-        &email_config.server,
-    )?;
+/// An indexed file picked for a message, with the root it was indexed under.
+pub struct SelectedFile {
+    pub path: PathBuf,
+    pub root: PathBuf,
+    pub meta: FileMetadata,
+}
 
-    Ok(())
+fn root_of(bof_dir: &Path) -> PathBuf {
+    bof_dir.parent().unwrap_or_else(|| Path::new(".")).to_path_buf()
 }
 
-fn find_bof_dir_for_path(file_path: &Path) -> Option<PathBuf> {
-    let mut current = file_path.parent();
+/// Resolve the `--file` arguments and the files whose names match `search`,
+/// in that order and without duplicates.
+pub fn select_files(file_paths: &[String], search: Option<&str>) -> io::Result<Vec<SelectedFile>> {
+    let mut selected: Vec<SelectedFile> = Vec::new();
+    let mut push = |file: SelectedFile| {
+        if !selected.iter().any(|s| s.path == file.path) {
+            selected.push(file);
+        }
+    };
 
-    while let Some(dir) = current {
-        let candidate = dir.join(".bof");
-        if candidate.is_dir() {
-            return Some(candidate);
+    for file_path in file_paths {
+        let path = canonicalize_path(Path::new(file_path))?;
+        let meta = find_indexed_file(&path)?;
+        let root = find_bof_dir_for_path(&path).map(|b| root_of(&b)).unwrap_or_default();
+        push(SelectedFile { path, root, meta });
+    }
+
+    if let Some(pattern) = search {
+        for result in search_by_name(pattern)? {
+            for dir in &result.directories {
+                let root = PathBuf::from(dir);
+                let (files, _) = load_metadata(&root.join(".bof"))?;
+                for meta in files {
+                    let name = Path::new(&meta.path).file_name().map(|n| n.to_string_lossy().to_string());
+                    if name.as_deref() == Some(result.file_name.as_str()) {
+                        push(SelectedFile { path: root.join(&meta.path), root: root.clone(), meta });
+                    }
+                }
+            }
         }
-        current = dir.parent();
     }
-    None
+
+    if selected.is_empty() {
+        return Err(io::Error::new(ErrorKind::NotFound, "No indexed files match the given --file/--search"));
+    }
+    Ok(selected)
 }
 
-fn load_email_config() -> io::Result<EmailConfig> {
-    let global_bof_dir = get_global_bof_dir()?;
-    let config_path = global_bof_dir.join("email_config.json");
+/// Rename `name` to `stem-2.ext`, `stem-3.ext`, ... until it is not in `taken`.
+fn unique_name(name: &str, taken: &mut HashSet<String>) -> String {
+    let path = Path::new(name);
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+
+    let mut candidate = name.to_string();
+    let mut n = 2;
+    while !taken.insert(candidate.clone()) {
+        candidate = format!("{}-{}{}", stem, n, ext);
+        n += 1;
+    }
+    candidate
+}
+
+fn build_attachments(files: &[SelectedFile], options: &AttachOptions) -> io::Result<Vec<EmailAttachment>> {
+    let mut attachments = Vec::new();
+    let mut taken = HashSet::new();
+
+    if options.attach_files {
+        // Check the sizes on disk first, so an oversized file is refused before
+        // it is read into memory.
+        let on_disk = files
+            .iter()
+            .map(|file| fs::metadata(&file.path).map(|m| m.len()))
+            .sum::<io::Result<u64>>()?;
+        check_attachment_size(on_disk, options.max_size)?;
+
+        for file in files {
+            let mut attachment = file_attachment(&file.path)?;
+            attachment.file_name = unique_name(&attachment.file_name, &mut taken);
+            attachments.push(attachment);
+        }
+    }
+    if options.zip && !attachments.is_empty() {
+        taken.clear();
+        attachments = vec![zip_attachments("files.zip", attachments)?];
+        taken.insert("files.zip".to_string());
+    }
 
-    if !config_path.exists() {
-        return Err(io::Error::new(
-            ErrorKind::NotFound,
-            format!("Global email config not found at {}", config_path.display()),
-        ));
+    if let Some(format) = options.manifest {
+        let roots: BTreeSet<&PathBuf> = files.iter().map(|f| &f.root).collect();
+        for root in &roots {
+            let (listing, _) = load_metadata(&root.join(".bof"))?;
+            let mut manifest = manifest_attachment(&listing, format)?;
+            if roots.len() > 1 {
+                let root_name = root.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default();
+                manifest.file_name = format!("{}-{}", root_name, manifest.file_name);
+            }
+            manifest.file_name = unique_name(&manifest.file_name, &mut taken);
+            attachments.push(manifest);
+        }
     }
 
-    let data = fs::read_to_string(&config_path)?;
-    let cfg: EmailConfig = serde_json::from_str(&data).map_err(|e| {
+    check_attachment_size(attachments.iter().map(|a| a.data.len() as u64).sum(), options.max_size)?;
+    Ok(attachments)
+}
+
+/// Send one message describing every selected file to all `recipients`.
+pub fn send_file_metadata_email(
+    profile: Option<&str>,
+    file_paths: &[String],
+    search: Option<&str>,
+    recipients: &Recipients,
+    options: &AttachOptions,
+) -> io::Result<usize> {
+    let files = select_files(file_paths, search)?;
+    let attachments = build_attachments(&files, options)?;
+
+    let email_config = load_email_config(profile)?;
+    let metadata: Vec<FileMetadata> = files.iter().map(|f| f.meta.clone()).collect();
+    let email = render_email(&metadata)?;
+    send_email(&email_config, profile, recipients, &email, &attachments)?;
+
+    Ok(files.len())
+}
+
+fn smtp_error(stage: &str, server: &str, e: smtp::Error) -> io::Error {
+    let auth_failed = e
+        .status()
+        .is_some_and(|code| matches!(code.to_string().as_str(), "530" | "534" | "535"));
+
+    if auth_failed {
         io::Error::new(
-            ErrorKind::InvalidData,
-            format!("Could not parse config: {e}"),
+            ErrorKind::PermissionDenied,
+            format!("SMTP authentication with {} failed: {}", server, e),
         )
-    })?;
-
-    Ok(cfg)
+    } else if e.is_tls() {
+        io::Error::new(
+            ErrorKind::ConnectionAborted,
+            format!("TLS negotiation with {} failed: {}", server, e),
+        )
+    } else {
+        io::Error::other(format!("SMTP {} with {} failed: {}", stage, server, e))
+    }
 }
 
-fn get_python_script_path() -> PathBuf {
-    let manifest_dir = env!("CARGO_MANIFEST_DIR"); 
-    
-    let script_path = Path::new(manifest_dir)
-        .join("src")
-        .join("python")
-        .join("send_email.py");
-    
-    script_path
+/// Build an SMTP transport for `config`, using STARTTLS, implicit TLS, or a plain
+/// connection (meant for local test servers) as configured.
+pub fn build_transport(config: &EmailConfig) -> io::Result<SmtpTransport> {
+    let builder = match config.security {
+        SmtpSecurity::StartTls => SmtpTransport::starttls_relay(&config.server),
+        SmtpSecurity::Tls => SmtpTransport::relay(&config.server),
+        SmtpSecurity::Plain => Ok(SmtpTransport::builder_dangerous(&config.server)),
+    }
+    .map_err(|e| smtp_error("setup", &config.server, e))?;
+
+    let mut builder = builder.port(config.port).timeout(Some(SMTP_TIMEOUT));
+    if !config.password.is_empty() {
+        builder = builder.credentials(Credentials::new(config.address.clone(), config.password.clone()));
+    }
+
+    Ok(builder.build())
 }
 
-pub fn send_email_via_python(
-    outlook_username: &str,
-    outlook_password: &str,
-    from_address: &str,
-    to_address: &str,
-    body: &str,
-    smtp_server: &str,
+/// Send a message. If delivery fails, it is saved to the outbox for
+/// `bof email flush` to retry, and the error says so.
+pub fn send_email(
+    config: &EmailConfig,
+    profile: Option<&str>,
+    recipients: &Recipients,
+    email: &RenderedEmail,
+    attachments: &[EmailAttachment],
 ) -> io::Result<()> {
-    let python_script_path = get_python_script_path(); 
-
-    let status = Command::new("python")
-        .arg(python_script_path)
-        .arg(outlook_username)
-        .arg(outlook_password)
-        .arg(from_address)
-        .arg(to_address)
-        .arg(smtp_server)
-        .arg(body) 
-        .status()?;
-
-    if !status.success() {
-        return Err(io::Error::new(
-            ErrorKind::Other,
-            format!("Python send_email script failed with exit code: {}", status),
-        ));
-    }
-
-    println!("Email sent successfully via Python child process!");
+    let mut builder = Message::builder()
+        .from(parse_mailbox(&config.address)?)
+        .subject(email.subject.as_str());
+    for mailbox in &recipients.to {
+        builder = builder.to(mailbox.clone());
+    }
+    for mailbox in &recipients.cc {
+        builder = builder.cc(mailbox.clone());
+    }
+    for mailbox in &recipients.bcc {
+        builder = builder.bcc(mailbox.clone());
+    }
+
+    let message = match (&email.html, attachments.is_empty()) {
+        (None, true) => builder.header(ContentType::TEXT_PLAIN).body(email.text.clone()),
+        (Some(html), true) => builder.multipart(MultiPart::alternative_plain_html(email.text.clone(), html.clone())),
+        (html, false) => {
+            let mut mixed = match html {
+                Some(html) => {
+                    MultiPart::mixed().multipart(MultiPart::alternative_plain_html(email.text.clone(), html.clone()))
+                }
+                None => MultiPart::mixed().singlepart(SinglePart::plain(email.text.clone())),
+            };
+            for attachment in attachments {
+                mixed = mixed.singlepart(
+                    Attachment::new(attachment.file_name.clone())
+                        .body(attachment.data.clone(), attachment.content_type.clone()),
+                );
+            }
+            builder.multipart(mixed)
+        }
+    }
+    .map_err(|e| io::Error::new(ErrorKind::InvalidInput, format!("Could not build email: {e}")))?;
+
+    let delivered = build_transport(config)
+        .and_then(|transport| transport.send(&message).map_err(|e| smtp_error("send", &config.server, e)));
+
+    if let Err(e) = delivered {
+        return match outbox::enqueue(profile, &email.subject, &message, &e) {
+            Ok(id) => Err(io::Error::new(e.kind(), outbox::QueuedError { id, cause: e })),
+            Err(save_error) => Err(io::Error::new(
+                e.kind(),
+                format!("{} (could not save it to the outbox: {})", e, save_error),
+            )),
+        };
+    }
+
     Ok(())
 }
@@ -0,0 +1,285 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use chrono::{Local, TimeZone};
+
+use crate::commands::global::get_global_bof_dir;
+use crate::commands::units::human_size;
+use crate::data_struct::FileMetadata;
+
+const SUBJECT_FILE: &str = "subject.txt";
+const TEXT_FILE: &str = "body.txt";
+const HTML_FILE: &str = "body.html";
+
+const EACH_START: &str = "{{#each}}";
+const EACH_END: &str = "{{/each}}";
+
+const DEFAULT_SUBJECT: &str = "File metadata: {{names}}";
+
+const DEFAULT_TEXT: &str = "Hello,
+
+Here is the metadata for the {{count}} file(s) you requested:
+{{#each}}
+Path: {{path}}
+Type: {{file_type}}
+Size: {{size_human}} ({{size}} bytes)
+Hash: {{hash}}
+Created: {{ctime_local}}
+Modified: {{mtime_local}}
+{{/each}}";
+
+const DEFAULT_HTML: &str = "<p>Hello,</p>
+<p>Here is the metadata for the {{count}} file(s) you requested:</p>
+{{#each}}
+<table>
+  <tr><th align=\"left\">Path</th><td>{{path}}</td></tr>
+  <tr><th align=\"left\">Type</th><td>{{file_type}}</td></tr>
+  <tr><th align=\"left\">Size</th><td>{{size_human}} ({{size}} bytes)</td></tr>
+  <tr><th align=\"left\">Hash</th><td><code>{{hash}}</code></td></tr>
+  <tr><th align=\"left\">Created</th><td>{{ctime_local}}</td></tr>
+  <tr><th align=\"left\">Modified</th><td>{{mtime_local}}</td></tr>
+</table>
+{{/each}}";
+
+/// Placeholders available for each file, as listed by `bof config-template`.
+pub const PLACEHOLDERS: &[&str] = &[
+    "key", "path", "name", "file_type", "size", "size_human", "hash",
+    "ctime", "mtime", "ctime_local", "mtime_local", "inode", "device",
+];
+
+/// Placeholders describing all the files of a message together.
+pub const SUMMARY_PLACEHOLDERS: &[&str] = &["count", "names", "total_size", "total_size_human"];
+
+/// A subject line plus a plain-text body and, if an HTML template exists, an HTML alternative.
+pub struct RenderedEmail {
+    pub subject: String,
+    pub text: String,
+    pub html: Option<String>,
+}
+
+pub fn get_templates_dir() -> io::Result<PathBuf> {
+    Ok(get_global_bof_dir()?.join("templates"))
+}
+
+/// Format Unix seconds in the local time zone; 0 means the time is unknown.
+pub fn local_time(secs: u64) -> String {
+    if secs == 0 {
+        return "unknown".to_string();
+    }
+    match Local.timestamp_opt(secs as i64, 0).single() {
+        Some(t) => t.format("%Y-%m-%d %H:%M:%S %:z").to_string(),
+        None => secs.to_string(),
+    }
+}
+
+pub fn file_placeholders(meta: &FileMetadata) -> Vec<(&'static str, String)> {
+    let optional = |v: Option<u64>| v.map(|v| v.to_string()).unwrap_or_default();
+    vec![
+        ("key", meta.key.clone()),
+        ("path", meta.path.clone()),
+        (
+            "name",
+            Path::new(&meta.path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default(),
+        ),
+        ("file_type", meta.file_type.clone()),
+        ("size", meta.size.to_string()),
+        ("size_human", human_size(meta.size)),
+        ("hash", meta.hash.clone().unwrap_or_else(|| "unknown".to_string())),
+        ("ctime", meta.ctime.to_string()),
+        ("mtime", meta.mtime.to_string()),
+        ("ctime_local", local_time(meta.ctime)),
+        ("mtime_local", local_time(meta.mtime)),
+        ("inode", optional(meta.inode)),
+        ("device", optional(meta.device)),
+    ]
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+/// Replace each `{{name}}` in `template` with its value. Unknown placeholders are
+/// left untouched so typos show up in the sent message.
+pub fn render(template: &str, values: &[(&str, String)], html: bool) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let Some(end) = after.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+
+        let name = after[..end].trim();
+        match values.iter().find(|(key, _)| *key == name) {
+            Some((_, value)) if html => out.push_str(&escape_html(value)),
+            Some((_, value)) => out.push_str(value),
+            None => out.push_str(&rest[start..start + 2 + end + 2]),
+        }
+        rest = &after[end + 2..];
+    }
+
+    out.push_str(rest);
+    out
+}
+
+fn read_template(dir: &Path, file_name: &str) -> io::Result<Option<String>> {
+    let path = dir.join(file_name);
+    if path.exists() {
+        Ok(Some(fs::read_to_string(path)?))
+    } else {
+        Ok(None)
+    }
+}
+
+fn summary_placeholders(files: &[FileMetadata]) -> Vec<(&'static str, String)> {
+    let total_size: u64 = files.iter().map(|f| f.size).sum();
+    let names: Vec<String> = files
+        .iter()
+        .map(|f| {
+            Path::new(&f.path)
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+        .collect();
+
+    vec![
+        ("count", files.len().to_string()),
+        ("names", names.join(", ")),
+        ("total_size", total_size.to_string()),
+        ("total_size_human", human_size(total_size)),
+    ]
+}
+
+/// Render a body for several files. The part between `{{#each}}` and `{{/each}}`
+/// is repeated per file; the rest sees the summary placeholders and those of the
+/// first file. A template without an each-block is repeated whole for every file.
+fn render_body(template: &str, files: &[FileMetadata], html: bool) -> String {
+    let summary = summary_placeholders(files);
+    let with_summary = |file: Option<&FileMetadata>| {
+        let mut values = file.map(file_placeholders).unwrap_or_default();
+        values.extend(summary.iter().cloned());
+        values
+    };
+
+    let each_block = template.find(EACH_START).and_then(|start| {
+        let end = start + template[start..].find(EACH_END)?;
+        Some((&template[..start], &template[start + EACH_START.len()..end], &template[end + EACH_END.len()..]))
+    });
+
+    match each_block {
+        Some((before, item, after)) => {
+            let outer = with_summary(files.first());
+            let items: String = files
+                .iter()
+                .map(|f| render(item, &with_summary(Some(f)), html))
+                .collect();
+            format!("{}{}{}", render(before, &outer, html), items, render(after, &outer, html))
+        }
+        None => files
+            .iter()
+            .map(|f| render(template, &with_summary(Some(f)), html))
+            .collect::<Vec<_>>()
+            .join("\n"),
+    }
+}
+
+/// Render the subject and bodies for `files` from the user's templates, falling
+/// back to the built-in ones. An HTML part is only produced from a user template.
+pub fn render_email(files: &[FileMetadata]) -> io::Result<RenderedEmail> {
+    let dir = get_templates_dir()?;
+    let subject = read_template(&dir, SUBJECT_FILE)?.unwrap_or_else(|| DEFAULT_SUBJECT.to_string());
+    let text = read_template(&dir, TEXT_FILE)?.unwrap_or_else(|| DEFAULT_TEXT.to_string());
+    let html = read_template(&dir, HTML_FILE)?;
+
+    let mut subject_values = files.first().map(file_placeholders).unwrap_or_default();
+    subject_values.extend(summary_placeholders(files));
+
+    Ok(RenderedEmail {
+        // Headers are single-line.
+        subject: render(subject.lines().next().unwrap_or_default(), &subject_values, false),
+        text: render_body(&text, files, false),
+        html: html.map(|h| render_body(&h, files, true)),
+    })
+}
+
+/// Write the built-in templates into the templates directory, keeping any that
+/// already exist, and return the directory.
+pub fn init_templates() -> io::Result<PathBuf> {
+    let dir = get_templates_dir()?;
+    fs::create_dir_all(&dir)?;
+
+    for (file_name, contents) in [(SUBJECT_FILE, DEFAULT_SUBJECT), (TEXT_FILE, DEFAULT_TEXT), (HTML_FILE, DEFAULT_HTML)] {
+        let path = dir.join(file_name);
+        if !path.exists() {
+            fs::write(&path, contents)?;
+            println!("Wrote {}", path.display());
+        }
+    }
+
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64) -> FileMetadata {
+        FileMetadata {
+            key: format!("key-{}", path),
+            path: path.to_string(),
+            file_type: "file".to_string(),
+            ctime: 0,
+            mtime: 0,
+            mtime_nsec: 0,
+            size,
+            hash: Some(format!("sha256:{}", size)),
+            inode: None,
+            device: None,
+        }
+    }
+
+    #[test]
+    fn render_replaces_known_placeholders_and_keeps_unknown_ones() {
+        let values = [("name", "a.txt".to_string()), ("size", "3".to_string())];
+        assert_eq!(render("{{name}} is {{ size }} bytes, {{colour}}", &values, false), "a.txt is 3 bytes, {{colour}}");
+    }
+
+    #[test]
+    fn render_escapes_values_for_html_only() {
+        let values = [("name", "<b>&'\"".to_string())];
+        assert_eq!(render("<p>{{name}}</p>", &values, true), "<p>&lt;b&gt;&amp;&#39;&quot;</p>");
+        assert_eq!(render("{{name}}", &values, false), "<b>&'\"");
+    }
+
+    #[test]
+    fn render_leaves_an_unclosed_placeholder_alone() {
+        let values = [("name", "a.txt".to_string())];
+        assert_eq!(render("{{name}} and {{name", &values, false), "a.txt and {{name");
+    }
+
+    #[test]
+    fn render_body_repeats_the_each_block_per_file() {
+        let files = [file("./a.txt", 1), file("./sub/b.txt", 2048)];
+        let body = render_body("{{count}} files ({{total_size}} B):\n{{#each}}- {{name}} {{size_human}}\n{{/each}}done", &files, false);
+        assert_eq!(body, "2 files (2049 B):\n- a.txt 1 B\n- b.txt 2.0 KiB\ndone");
+    }
+
+    #[test]
+    fn render_body_without_each_block_repeats_the_template() {
+        let files = [file("./a.txt", 1), file("./b.txt", 2)];
+        assert_eq!(render_body("{{name}} of {{count}}", &files, false), "a.txt of 2\nb.txt of 2");
+    }
+}
@@ -1,3 +1,5 @@
+use std::collections::{BTreeMap, BTreeSet};
+
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -7,19 +9,154 @@ pub struct FileMetadata {
     pub file_type: String,
     pub ctime: u64,
     pub mtime: u64,
+    /// Sub-second part of `mtime`, so edits within the same second are noticed.
+    #[serde(default)]
+    pub mtime_nsec: u32,
     pub size: u64,
+    #[serde(default)]
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub inode: Option<u64>,
+    #[serde(default)]
+    pub device: Option<u64>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DirectoryEntry {
+    pub key: String,
+    pub kind: String,
+    pub name: String,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct DirectoryMetadata {
     pub key: String,
-    pub entries: Vec<(String, String, String)>, 
+    pub path: String,
+    pub parent: Option<String>,
+    pub entries: Vec<DirectoryEntry>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SmtpSecurity {
+    #[default]
+    StartTls,
+    Tls,
+    Plain,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EncryptedSecret {
+    pub iterations: u32,
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct EmailConfig {
     pub address: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
     pub password: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub encrypted_password: Option<EncryptedSecret>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_env: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password_command: Option<String>,
     pub server: String,
     pub port: u16,
-}
\ No newline at end of file
+    #[serde(default)]
+    pub security: SmtpSecurity,
+}
+
+/// Named SMTP configurations stored in `email_config.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct EmailProfiles {
+    #[serde(default)]
+    pub default: Option<String>,
+    #[serde(default)]
+    pub profiles: BTreeMap<String, EmailConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum OutboxStatus {
+    /// Will be retried by `bof email flush` once `next_attempt` has passed.
+    Pending,
+    /// Gave up after too many attempts; only retried with `flush --all`.
+    Failed,
+}
+
+/// A message that could not be delivered, kept in `~/bof_global/outbox`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct OutboxMessage {
+    pub id: String,
+    #[serde(default)]
+    pub profile: Option<String>,
+    pub from: String,
+    pub recipients: Vec<String>,
+    pub subject: String,
+    pub created: u64,
+    pub attempts: u32,
+    pub next_attempt: u64,
+    #[serde(default)]
+    pub last_error: Option<String>,
+    pub status: OutboxStatus,
+    /// The formatted message, base64-encoded, exactly as it was first sent.
+    pub message: String,
+}
+
+/// What `bof digest` last reported for one file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct DigestEntry {
+    #[serde(default)]
+    pub hash: Option<String>,
+    pub size: u64,
+}
+
+/// The state of every indexed root when the last digest was sent, kept in
+/// `~/bof_global/digest_state.json`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DigestState {
+    #[serde(default)]
+    pub last_digest: u64,
+    /// Root folder -> stored file path -> entry.
+    #[serde(default)]
+    pub roots: BTreeMap<String, BTreeMap<String, DigestEntry>>,
+}
+
+/// An HTTP endpoint that receives JSON notifications.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Environment variable holding the HMAC secret; requests are unsigned without it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub secret_env: Option<String>,
+    /// Also notify after every `bof index` run that changed something.
+    #[serde(default)]
+    pub on_index: bool,
+}
+
+/// Named webhooks stored in `webhooks.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct WebhookConfigs {
+    #[serde(default)]
+    pub webhooks: BTreeMap<String, WebhookConfig>,
+}
+
+/// Inverted index of the words in a root's text files, kept in `.bof/content.json`.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct ContentIndex {
+    /// Files larger than this are left out.
+    pub max_size: u64,
+    /// Stored path -> hash of the version whose words are indexed.
+    #[serde(default)]
+    pub documents: BTreeMap<String, String>,
+    /// Lowercased word -> stored paths of the files containing it.
+    #[serde(default)]
+    pub terms: BTreeMap<String, BTreeSet<String>>,
+    /// Stored path -> hash of a version found not to be text, so it is not re-read.
+    #[serde(default)]
+    pub skipped: BTreeMap<String, String>,
+}
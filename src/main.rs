@@ -1,9 +1,10 @@
-mod data_struct;
-mod commands;
-
-use clap::{Command, Arg};
-use std::path::Path;
-use commands::{init, index, find_file, clear, search, email_config, send_email};
+use clap::{Command, Arg, ArgAction, ArgGroup};
+use std::process;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use bof_test::commands::{init, index, content, digest, find_file, clear, search, query, email_config, email_test, outbox, send_email, notify, webhook, template, units};
+use bof_test::commands::attachment::ManifestFormat;
+use bof_test::data_struct;
 
 fn main() {
     let matches = Command::new("bof")
@@ -12,11 +13,48 @@ fn main() {
         .about("A tool to index and manage file metadata")
         .subcommand(
             Command::new("init")
-                .about("Initialize a .bof directory in the current folder"),
+                .about("Initialize a .bof directory in each given folder (default: the current one)")
+                .arg(paths_arg()),
         )
         .subcommand(
             Command::new("index")
-                .about("Index files and directories in the current folder"),
+                .about("Index files and directories in each given folder (default: the current one)")
+                .arg(paths_arg())
+                .arg(
+                    Arg::new("jobs")
+                        .long("jobs")
+                        .short('j')
+                        .help("Number of files to hash in parallel (defaults to the CPU count)")
+                        .value_parser(clap::value_parser!(usize))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("gitignore")
+                        .long("gitignore")
+                        .help("Also skip paths matched by .gitignore files")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("exclude")
+                        .long("exclude")
+                        .help("Gitignore-style pattern to skip for this run (repeatable)")
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("content")
+                        .long("content")
+                        .help("Also index the words of text files for 'bof grep' (kept up to date afterwards)")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("content-max-size")
+                        .long("content-max-size")
+                        .help("Skip text files larger than this in the content index, e.g. 512KB (default 1MB)")
+                        .requires("content")
+                        .num_args(1),
+                )
+                .arg(wait_arg()),
         )
         .subcommand(
             Command::new("findfile")
@@ -30,19 +68,96 @@ fn main() {
         )
         .subcommand(
             Command::new("clear-all")
-                .about("Remove all .bof directories listed in the inverse table"),
+                .about("Remove all .bof directories listed in the inverse table")
+                .arg(wait_arg()),
         )
         .subcommand(
             Command::new("search") 
-                .about("Search for files by partial name")
+                .about("Search for files by partial name, glob or regex")
                 .arg(Arg::new("pattern")
-                    .help("The substring to match against filenames")
+                    .help("The substring to match against filenames (or glob/regex with --glob/--regex)")
                     .required(true)
                     .index(1))
+                .arg(
+                    Arg::new("glob")
+                        .long("glob")
+                        .help("Treat the pattern as a glob matching the whole name, e.g. '*.tar.gz'")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("regex")
+                        .long("regex")
+                        .help("Treat the pattern as a regular expression, e.g. '^report_\\d{4}'")
+                        .conflicts_with("glob")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("case-sensitive")
+                        .long("case-sensitive")
+                        .help("Match upper and lower case exactly")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("fuzzy")
+                        .long("fuzzy")
+                        .help("Rank names by how closely they match, tolerating gaps and typos, e.g. 'rprt'")
+                        .conflicts_with_all(["glob", "regex"])
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("path")
+                        .long("path")
+                        .help("Match the pattern against each file's path below its indexed folder, e.g. 'src/*.rs' with --glob")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("limit")
+                        .long("limit")
+                        .help("Show at most this many results")
+                        .value_parser(clap::value_parser!(usize))
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("query")
+                        .long("query")
+                        .help("Treat the pattern as a metadata query, e.g. 'name:*.pdf size>10MB modified<30d'")
+                        .conflicts_with_all(["glob", "regex", "fuzzy", "case-sensitive", "path"])
+                        .action(ArgAction::SetTrue),
+                )
+        )
+        .subcommand(
+            Command::new("grep")
+                .about("Search the content index of every indexed folder for files containing all given words")
+                .arg(
+                    Arg::new("terms")
+                        .help("Words that must all appear in a file")
+                        .required(true)
+                        .num_args(1..),
+                ),
         )
         .subcommand(
             Command::new("config-email")
-                .about("Save SMTP config (email, password, server, etc.)")
+                .about("Save SMTP config (email, password, server, etc.) as a named profile")
+                .subcommand_negates_reqs(true)
+                .args_conflicts_with_subcommands(true)
+                .subcommand(Command::new("list").about("List email profiles; the default one is marked with *"))
+                .subcommand(
+                    Command::new("remove")
+                        .about("Delete an email profile")
+                        .arg(Arg::new("name").help("Profile to delete").required(true).index(1)),
+                )
+                .subcommand(
+                    Command::new("default")
+                        .about("Make a profile the default for send-email")
+                        .arg(Arg::new("name").help("Profile to use by default").required(true).index(1)),
+                )
+                .arg(profile_arg().help("Profile to create or update (defaults to the default profile)"))
+                .arg(
+                    Arg::new("default")
+                        .long("default")
+                        .help("Also make this profile the default")
+                        .action(ArgAction::SetTrue),
+                )
                 .arg(
                     Arg::new("address")
                         .long("address")
@@ -51,11 +166,27 @@ fn main() {
                         .num_args(1), 
                 )
                 .arg(
-                    Arg::new("password")
-                        .long("password")
-                        .help("Password or app-specific password")
-                        .required(true)
-                        .num_args(1), 
+                    Arg::new("password-stdin")
+                        .long("password-stdin")
+                        .help("Read the password from stdin (or a hidden prompt) and store it encrypted with a passphrase")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("password-env")
+                        .long("password-env")
+                        .help("Name of an environment variable holding the password when sending")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("password-command")
+                        .long("password-command")
+                        .help("Shell command that prints the password when sending, e.g. 'pass show smtp'")
+                        .num_args(1),
+                )
+                .group(
+                    ArgGroup::new("password")
+                        .args(["password-stdin", "password-env", "password-command"])
+                        .required(true),
                 )
                 .arg(
                     Arg::new("server")
@@ -71,47 +202,304 @@ fn main() {
                         .help("SMTP port, e.g. 587 for STARTTLS")
                         .default_value("587")
                         .num_args(1),
+                )
+                .arg(
+                    Arg::new("security")
+                        .long("security")
+                        .help("Connection security: starttls, tls (implicit, port 465) or plain (local test servers only)")
+                        .value_parser(["starttls", "tls", "plain"])
+                        .num_args(1),
+                ),
+        )
+        .subcommand(
+            Command::new("email")
+                .about("Check and manage outgoing email")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("test")
+                        .about("Connect, negotiate TLS and authenticate with an email profile, reporting the stage that fails")
+                        .arg(profile_arg().help("Email profile to test (defaults to the default profile)"))
+                        .arg(
+                            Arg::new("send-to")
+                                .long("send-to")
+                                .help("Also send a short test message to this address")
+                                .num_args(1),
+                        ),
+                )
+                .subcommand(
+                    Command::new("flush")
+                        .about("Retry sending the messages in the outbox whose retry time has come")
+                        .arg(
+                            Arg::new("all")
+                                .long("all")
+                                .help("Retry every message now, including ones not due yet or given up on")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(wait_arg()),
+                )
+                .subcommand(
+                    Command::new("outbox")
+                        .about("List messages waiting in the outbox")
+                        .arg(
+                            Arg::new("remove")
+                                .long("remove")
+                                .help("Delete the message with this id instead of listing")
+                                .num_args(1),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("digest")
+                .about("Summarize what changed across indexed folders since the last digest")
+                .arg(
+                    Arg::new("send")
+                        .long("send")
+                        .help("Email the digest and start the next one from now (without it, only print)")
+                        .requires("to")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("Recipient of the digest email (repeatable)")
+                        .requires("send")
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(profile_arg().help("Email profile to send with (defaults to the default profile)")),
+        )
+        .subcommand(
+            Command::new("config-webhook")
+                .about("Save a named webhook that receives JSON notifications")
+                .subcommand_negates_reqs(true)
+                .args_conflicts_with_subcommands(true)
+                .subcommand(Command::new("list").about("List configured webhooks"))
+                .subcommand(
+                    Command::new("remove")
+                        .about("Delete a webhook")
+                        .arg(Arg::new("name").help("Webhook to delete").required(true).index(1)),
+                )
+                .arg(
+                    Arg::new("name")
+                        .long("name")
+                        .help("Name of the webhook to create or update")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("url")
+                        .long("url")
+                        .help("http:// or https:// URL to POST notifications to")
+                        .required(true)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("secret-env")
+                        .long("secret-env")
+                        .help("Environment variable holding the secret to sign requests with (HMAC-SHA256)")
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("on-index")
+                        .long("on-index")
+                        .help("Also notify after each 'bof index' run that changed files")
+                        .action(ArgAction::SetTrue),
                 ),
         )
+        .subcommand(
+            Command::new("notify")
+                .about("Send the metadata of one or more files to webhooks and/or by email")
+                .arg(
+                    Arg::new("file")
+                        .long("file")
+                        .help("File to send metadata for (repeatable)")
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("search")
+                        .long("search")
+                        .help("Also include every indexed file whose name contains this pattern")
+                        .num_args(1),
+                )
+                .group(
+                    ArgGroup::new("files")
+                        .args(["file", "search"])
+                        .multiple(true)
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("webhook")
+                        .long("webhook")
+                        .help("Webhook to notify (repeatable; defaults to all of them unless --to is given)")
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .help("Also notify this email address (repeatable)")
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(profile_arg().help("Email profile to send with (defaults to the default profile)")),
+        )
+        .subcommand(
+            Command::new("config-template")
+                .about("Write the default email templates to ~/bof_global/templates for editing"),
+        )
         // NEW: send-email
         .subcommand(
             Command::new("send-email")
-                .about("Send an email with the metadata of a specified file")
+                .about("Send an email with the metadata of one or more files")
+                .arg(profile_arg().help("Email profile to send with (defaults to the default profile)"))
                 .arg(
                     Arg::new("file")
                         .long("file")
-                        .help("Which file to fetch metadata for")
-                        .required(true)
+                        .help("Which file to fetch metadata for (repeatable)")
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("search")
+                        .long("search")
+                        .help("Also include every indexed file whose name contains this pattern")
                         .num_args(1),
                 )
+                .group(
+                    ArgGroup::new("files")
+                        .args(["file", "search"])
+                        .multiple(true)
+                        .required(true),
+                )
                 .arg(
                     Arg::new("to")
                         .long("to")
-                        .help("Recipient's email address")
+                        .help("Recipient's email address (repeatable)")
                         .required(true)
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("cc")
+                        .long("cc")
+                        .help("Carbon-copy recipient (repeatable)")
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("bcc")
+                        .long("bcc")
+                        .help("Blind carbon-copy recipient (repeatable)")
+                        .action(ArgAction::Append)
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("attach")
+                        .long("attach")
+                        .help("Attach the file itself")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("zip")
+                        .long("zip")
+                        .help("Bundle attached files into a single files.zip")
+                        .requires("attach")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("manifest")
+                        .long("manifest")
+                        .help("Attach a listing of the folder's .bof/files.json")
+                        .value_parser(["csv", "json"])
+                        .num_args(1),
+                )
+                .arg(
+                    Arg::new("max-attachment-size")
+                        .long("max-attachment-size")
+                        .help("Refuse to send if attachments exceed this size, e.g. 10MB")
+                        .default_value("20MB")
                         .num_args(1),
                 ),
         )
         .get_matches();
 
     match matches.subcommand() {
-        Some(("init", _)) => {
-            let current_dir = Path::new(".");
-            if let Err(e) = init::init_bof_directory(current_dir) {
-                eprintln!("Failed to initialize .bof directory: {}", e);
+        Some(("init", sub_matches)) => {
+            for root in get_roots(sub_matches) {
+                if let Err(e) = init::init_bof_directory(&root) {
+                    eprintln!("Failed to initialize .bof directory in {}: {}", root.display(), e);
+                }
             }
         }
-        Some(("index", _)) => {
-            let current_dir = Path::new(".");
-            let bof_dir = current_dir.join(".bof");
-            if let Err(e) = index::collect_metadata(current_dir, &bof_dir) {
-                eprintln!("Failed to index files: {}", e);
+        Some(("index", sub_matches)) => {
+            let content_max_size = match sub_matches.get_one::<String>("content-max-size") {
+                Some(size) => match units::parse_size(size) {
+                    Ok(size) => Some(size),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                },
+                None if sub_matches.get_flag("content") => Some(content::DEFAULT_MAX_CONTENT_SIZE),
+                None => None,
+            };
+            let options = index::IndexOptions {
+                lock_wait: get_wait(sub_matches),
+                jobs: sub_matches.get_one::<usize>("jobs").copied(),
+                use_gitignore: sub_matches.get_flag("gitignore"),
+                excludes: get_strings(sub_matches, "exclude"),
+                content_max_size,
+            };
+            let notifiers = notify::index_notifiers().unwrap_or_else(|e| {
+                eprintln!("Not notifying webhooks: {}", e);
+                Vec::new()
+            });
+            let roots = get_roots(sub_matches);
+            // Folders that don't exist were already reported by get_roots.
+            let mut failed = roots.len() < sub_matches.get_many::<String>("paths").map_or(0, |p| p.len());
+            for root in roots {
+                let bof_dir = root.join(".bof");
+                let before = if notifiers.is_empty() {
+                    Vec::new()
+                } else {
+                    index::load_metadata(&bof_dir).map(|(files, _)| files).unwrap_or_default()
+                };
+                match index::collect_metadata(&root, &bof_dir, &options) {
+                    Ok(summary) => {
+                        println!(
+                            "{}: indexed {} files ({} rehashed, {} unchanged and skipped, {} removed).",
+                            root.display(),
+                            summary.rehashed + summary.skipped,
+                            summary.rehashed,
+                            summary.skipped,
+                            summary.removed
+                        );
+                        if let Some(read) = summary.content_indexed {
+                            println!("{}: content index updated ({} files read).", root.display(), read);
+                        }
+                        if !notifiers.is_empty() {
+                            match notify::index_change_event(&root, before) {
+                                Ok(Some(event)) => notify::notify_all(&notifiers, &event),
+                                Ok(None) => {}
+                                Err(e) => eprintln!("Could not compute changes in {}: {}", root.display(), e),
+                            }
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to index files in {}: {}", root.display(), e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed {
+                process::exit(1);
             }
         }
         Some(("findfile", sub_matches)) => {
             let file_path = sub_matches.get_one::<String>("file").unwrap();
 
-            let file_hash = match find_file::compute_file_hash(Path::new(file_path)) {
+            let file_hash = match find_file::file_hash_key(Path::new(file_path)) {
                 Ok(hash) => hash,
                 Err(e) => {
                     eprintln!("Failed to compute file hash: {}", e);
@@ -135,26 +523,85 @@ fn main() {
                 }
             }
         }
-        Some(("clear-all", _)) => {
-            if let Err(e) = clear::clear_all_bof_dirs() {
+        Some(("clear-all", sub_matches)) => {
+            if let Err(e) = clear::clear_all_bof_dirs(get_wait(sub_matches)) {
                 eprintln!("{}", e);
             }
         }
         Some(("search", sub_matches)) => {
             let pattern = sub_matches.get_one::<String>("pattern").unwrap();
-            match search::search_by_name(pattern) {
+            let limit = sub_matches.get_one::<usize>("limit").copied().unwrap_or(usize::MAX);
+            if sub_matches.get_flag("query") {
+                let results = query::parse_query(pattern).and_then(|q| query::run_query(&q));
+                let results = results.map(|mut records| {
+                    records.truncate(limit);
+                    records
+                });
+                match results {
+                    Ok(records) if records.is_empty() => println!("No files match '{}'.", pattern),
+                    Ok(records) => {
+                        println!("Found {} files matching '{}':", records.len(), pattern);
+                        for record in records {
+                            println!(
+                                "{}  {}  modified {}",
+                                record.location.display(),
+                                units::human_size(record.meta.size),
+                                template::local_time(record.meta.mtime)
+                            );
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to search file: {}", e),
+                }
+                return;
+            }
+            let case_sensitive = sub_matches.get_flag("case-sensitive");
+            let matcher = if sub_matches.get_flag("glob") {
+                search::NameMatcher::glob(pattern, case_sensitive)
+            } else if sub_matches.get_flag("regex") {
+                search::NameMatcher::regex(pattern, case_sensitive)
+            } else if sub_matches.get_flag("fuzzy") {
+                Ok(search::NameMatcher::fuzzy(pattern, case_sensitive))
+            } else {
+                Ok(search::NameMatcher::substring(pattern, case_sensitive))
+            };
+            let matcher = match matcher {
+                Ok(matcher) => matcher,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+
+            let target = if sub_matches.get_flag("path") {
+                search::SearchTarget::Path
+            } else {
+                search::SearchTarget::Name
+            };
+            let label = match target {
+                search::SearchTarget::Name => "File name",
+                search::SearchTarget::Path => "Path",
+            };
+
+            match search::search(&matcher, target) {
                 Ok(matches) => {
                     if matches.is_empty() {
                         println!("No files match '{}'.", pattern);
                     } else {
-                        println!("Found {} matches for '{}':\\n", matches.len(), pattern);
-                        for entry in matches {
-                            println!("File name: {}", entry.file_name);
+                        if matches.len() > limit {
+                            println!("Found {} matches for '{}', showing the first {}:\n", matches.len(), pattern, limit);
+                        } else {
+                            println!("Found {} matches for '{}':\n", matches.len(), pattern);
+                        }
+                        for entry in matches.into_iter().take(limit) {
+                            match entry.score {
+                                Some(score) => println!("{}: {} (score {})", label, entry.file_name, score),
+                                None => println!("{}: {}", label, entry.file_name),
+                            }
                             println!("Directories:");
                             for dir in entry.directories {
                                 println!("  - {}", dir);
                             }
-                            println!(""); 
+                            println!();
                         }
                     }
                 }
@@ -162,33 +609,309 @@ fn main() {
             }
         }
         Some(("config-email", sub_matches)) => {
+            let result = match sub_matches.subcommand() {
+                Some(("list", _)) => Some(email_config::list_profiles()),
+                Some(("remove", profile_matches)) => {
+                    Some(email_config::remove_profile(profile_matches.get_one::<String>("name").unwrap()))
+                }
+                Some(("default", profile_matches)) => {
+                    Some(email_config::set_default_profile(profile_matches.get_one::<String>("name").unwrap()))
+                }
+                _ => None,
+            };
+            if let Some(result) = result {
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                }
+                return;
+            }
+
+            let profile = sub_matches.get_one::<String>("profile").map(|s| s.as_str());
             let address = sub_matches.get_one::<String>("address").unwrap();
-            let password = sub_matches.get_one::<String>("password").unwrap();
+            let password_source = if let Some(var) = sub_matches.get_one::<String>("password-env") {
+                email_config::PasswordSource::Env(var.clone())
+            } else if let Some(command) = sub_matches.get_one::<String>("password-command") {
+                email_config::PasswordSource::Command(command.clone())
+            } else {
+                email_config::PasswordSource::Stdin
+            };
             let server = sub_matches.get_one::<String>("server").unwrap();
             let port = sub_matches.get_one::<String>("port").unwrap();
+            let security = sub_matches.get_one::<String>("security").map(|s| s.as_str());
 
-            match email_config::config_email_command(address, password, server, port) {
+            match email_config::config_email_command(
+                profile,
+                sub_matches.get_flag("default"),
+                address,
+                password_source, server, port, security) {
                 Ok(_) => println!("Email config saved successfully!"),
                 Err(e) => eprintln!("Failed to save email config: {}", e),
             }
         }
+        Some(("email", sub_matches)) => match sub_matches.subcommand() {
+            Some(("test", test_matches)) => {
+                let profile = test_matches.get_one::<String>("profile").map(|s| s.as_str());
+                let send_to = test_matches.get_one::<String>("send-to").map(|s| s.as_str());
+                match email_test::test_email_config(profile, send_to) {
+                    Ok(_) => println!("Email configuration works!"),
+                    Err(e) => eprintln!("Email test failed: {}", e),
+                }
+            }
+            Some(("flush", flush_matches)) => {
+                match outbox::flush_outbox(flush_matches.get_flag("all"), get_wait(flush_matches)) {
+                    Ok(summary) => println!(
+                        "Outbox flushed: {} sent, {} failed, {} waiting for their next retry.",
+                        summary.sent, summary.failed, summary.waiting
+                    ),
+                    Err(e) => eprintln!("Failed to flush the outbox: {}", e),
+                }
+            }
+            Some(("outbox", outbox_matches)) => {
+                if let Some(id) = outbox_matches.get_one::<String>("remove") {
+                    match outbox::remove_message(id) {
+                        Ok(_) => println!("Removed {} from the outbox.", id),
+                        Err(e) => eprintln!("{}", e),
+                    }
+                    return;
+                }
+                match outbox::load_outbox() {
+                    Ok(messages) if messages.is_empty() => println!("The outbox is empty."),
+                    Ok(messages) => {
+                        for message in messages {
+                            let status = match message.status {
+                                data_struct::OutboxStatus::Pending => {
+                                    format!("pending, next retry {}", template::local_time(message.next_attempt))
+                                }
+                                data_struct::OutboxStatus::Failed => "failed, retry with 'flush --all'".to_string(),
+                            };
+                            println!("{} ({}, {} attempt(s))", message.id, status, message.attempts);
+                            println!("  Profile: {}", message.profile.as_deref().unwrap_or("default"));
+                            println!("  To: {}", message.recipients.join(", "));
+                            println!("  Subject: {}", message.subject);
+                            if let Some(error) = &message.last_error {
+                                println!("  Last error: {}", error);
+                            }
+                        }
+                    }
+                    Err(e) => eprintln!("Failed to read the outbox: {}", e),
+                }
+            }
+            _ => {}
+        },
+        Some(("digest", sub_matches)) => {
+            let profile = sub_matches.get_one::<String>("profile").map(|s| s.as_str());
+            let recipients = if sub_matches.get_flag("send") {
+                match send_email::parse_recipients(&get_strings(sub_matches, "to"), &[], &[]) {
+                    Ok(recipients) => Some(recipients),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        process::exit(1);
+                    }
+                }
+            } else {
+                None
+            };
+
+            // A non-zero status lets cron and other schedulers notice a failed digest.
+            if let Err(e) = digest::run_digest(recipients.as_ref(), profile) {
+                eprintln!("Digest failed: {}", e);
+                process::exit(1);
+            }
+        }
+        Some(("config-webhook", sub_matches)) => {
+            let result = match sub_matches.subcommand() {
+                Some(("list", _)) => webhook::list_webhooks(),
+                Some(("remove", remove_matches)) => {
+                    webhook::remove_webhook(remove_matches.get_one::<String>("name").unwrap())
+                }
+                _ => webhook::config_webhook_command(
+                    sub_matches.get_one::<String>("name").unwrap(),
+                    sub_matches.get_one::<String>("url").unwrap(),
+                    sub_matches.get_one::<String>("secret-env").map(|s| s.as_str()),
+                    sub_matches.get_flag("on-index"),
+                ),
+            };
+            if let Err(e) = result {
+                eprintln!("{}", e);
+            }
+        }
+        Some(("notify", sub_matches)) => {
+            let webhooks = get_strings(sub_matches, "webhook");
+            let to = get_strings(sub_matches, "to");
+
+            let mut notifiers = if webhooks.is_empty() && !to.is_empty() {
+                Vec::new()
+            } else {
+                match notify::webhook_notifiers(&webhooks) {
+                    Ok(notifiers) => notifiers,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+            };
+            if !to.is_empty() {
+                match send_email::parse_recipients(&to, &[], &[]) {
+                    Ok(recipients) => notifiers.push(Box::new(notify::EmailNotifier {
+                        profile: sub_matches.get_one::<String>("profile").cloned(),
+                        recipients,
+                    })),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return;
+                    }
+                }
+            }
+            if notifiers.is_empty() {
+                eprintln!("Nothing to notify: configure a webhook with 'bof config-webhook' or pass --to.");
+                return;
+            }
+
+            let search = sub_matches.get_one::<String>("search").map(|s| s.as_str());
+            match send_email::select_files(&get_strings(sub_matches, "file"), search) {
+                Ok(files) => notify::notify_all(&notifiers, &notify::file_event(files)),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+        Some(("config-template", _)) => {
+            match template::init_templates() {
+                Ok(dir) => {
+                    println!("Email templates live in {}", dir.display());
+                    let placeholders: Vec<String> = template::PLACEHOLDERS
+                        .iter()
+                        .map(|p| format!("{{{{{}}}}}", p))
+                        .collect();
+                    println!("Available placeholders: {}", placeholders.join(", "));
+                    let summary: Vec<String> = template::SUMMARY_PLACEHOLDERS
+                        .iter()
+                        .map(|p| format!("{{{{{}}}}}", p))
+                        .collect();
+                    println!("For all files of a message: {}", summary.join(", "));
+                    println!("Repeat a section per file by wrapping it in {{{{#each}}}} ... {{{{/each}}}}");
+                }
+                Err(e) => eprintln!("Failed to write email templates: {}", e),
+            }
+        }
         Some(("send-email", sub_matches)) => {
-            let file_path = sub_matches.get_one::<String>("file").unwrap();
-            let recipient = sub_matches.get_one::<String>("to").unwrap();
+            let file_paths = get_strings(sub_matches, "file");
+            let profile = sub_matches.get_one::<String>("profile").map(|s| s.as_str());
+            let search = sub_matches.get_one::<String>("search").map(|s| s.as_str());
+            let recipients = match send_email::parse_recipients(
+                &get_strings(sub_matches, "to"),
+                &get_strings(sub_matches, "cc"),
+                &get_strings(sub_matches, "bcc"),
+            ) {
+                Ok(recipients) => recipients,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            let max_size = sub_matches.get_one::<String>("max-attachment-size").unwrap();
+            let max_size = match units::parse_size(max_size) {
+                Ok(size) => size,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return;
+                }
+            };
+            let options = send_email::AttachOptions {
+                attach_files: sub_matches.get_flag("attach"),
+                zip: sub_matches.get_flag("zip"),
+                manifest: match sub_matches.get_one::<String>("manifest").map(|s| s.as_str()) {
+                    Some("csv") => Some(ManifestFormat::Csv),
+                    Some("json") => Some(ManifestFormat::Json),
+                    _ => None,
+                },
+                max_size,
+            };
 
-            match send_email::send_file_metadata_email(file_path, recipient) {
-                Ok(_) => println!("Email sent!"),
+            match send_email::send_file_metadata_email(profile, &file_paths, search, &recipients, &options) {
+                Ok(count) => println!("Email sent with the metadata of {} file(s)!", count),
                 Err(e) => eprintln!("Failed to send email: {}", e),
             }
         }
+        Some(("grep", sub_matches)) => {
+            let terms = get_strings(sub_matches, "terms");
+            match content::grep(&terms) {
+                Ok(matches) if matches.is_empty() => {
+                    println!("No indexed text files contain '{}'.", terms.join(" "));
+                }
+                Ok(matches) => {
+                    println!("Found {} files containing '{}':", matches.len(), terms.join(" "));
+                    for found in matches {
+                        println!("\n{}", found.location.display());
+                        for (line, snippet) in found.snippets {
+                            println!("  {}: {}", line, snippet);
+                        }
+                    }
+                }
+                Err(e) => eprintln!("Failed to search contents: {}", e),
+            }
+        }
         _ => {
             println!("No valid command provided. Use one of:");
-            println!("  bof init");
-            println!("  bof index");
+            println!("  bof init [PATH]...");
+            println!("  bof index [--content [--content-max-size <SIZE>]] [PATH]...");
+            println!("  bof grep <WORD>...");
             println!("  bof findfile --file <FILE>");
             println!("  bof clear-all");
-            println!("  bof config-email --address <ADDRESS> --password <PASSWORD> --server <SMTP> --port <PORT>");
-            println!("  bof send-email --file <FILE> --to <RECIPIENT>");
+            println!("  bof config-email list | remove <NAME> | default <NAME>");
+            println!("  bof config-email [--profile <NAME>] [--default] --address <ADDRESS> (--password-stdin | --password-env <VAR> | --password-command <CMD>) --server <SMTP> --port <PORT>");
+            println!("  bof digest [--send --to <RECIPIENT>... [--profile <NAME>]]");
+            println!("  bof config-webhook --name <NAME> --url <URL> [--secret-env <VAR>] [--on-index] | list | remove <NAME>");
+            println!("  bof notify (--file <FILE>... | --search <PATTERN>) [--webhook <NAME>...] [--to <ADDRESS>...]");
+            println!("  bof email test [--profile <NAME>] [--send-to <ADDRESS>]");
+            println!("  bof email flush [--all] | bof email outbox [--remove <ID>]");
+            println!("  bof send-email [--profile <NAME>] (--file <FILE>... | --search <PATTERN>) --to <RECIPIENT>... [--cc <ADDRESS>...] [--bcc <ADDRESS>...]");
         }
     }
 }
+
+fn paths_arg() -> Arg {
+    Arg::new("paths")
+        .help("Folders to operate on")
+        .action(ArgAction::Append)
+        .default_value(".")
+        .num_args(1..)
+}
+
+/// Resolve each PATH argument to its canonical form, reporting the ones that don't exist.
+fn get_roots(sub_matches: &clap::ArgMatches) -> Vec<PathBuf> {
+    sub_matches
+        .get_many::<String>("paths")
+        .into_iter()
+        .flatten()
+        .filter_map(|p| match index::canonicalize_path(Path::new(p)) {
+            Ok(root) => Some(root),
+            Err(e) => {
+                eprintln!("Skipping {}: {}", p, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// All values of a repeatable option, in the order given.
+fn get_strings(sub_matches: &clap::ArgMatches, id: &str) -> Vec<String> {
+    sub_matches
+        .get_many::<String>(id)
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default()
+}
+
+fn profile_arg() -> Arg {
+    Arg::new("profile").long("profile").num_args(1)
+}
+
+fn wait_arg() -> Arg {
+    Arg::new("wait")
+        .long("wait")
+        .help("Seconds to wait for another bof process to finish instead of failing")
+        .value_parser(clap::value_parser!(u64))
+        .num_args(1)
+}
+
+fn get_wait(sub_matches: &clap::ArgMatches) -> Option<Duration> {
+    sub_matches.get_one::<u64>("wait").map(|secs| Duration::from_secs(*secs))
+}
@@ -0,0 +1,248 @@
+//! `send_email` and `test_email_config` against an in-process SMTP server
+//! speaking just enough of the protocol for lettre, over a plain connection.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Mutex, OnceLock};
+use std::thread;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use lettre::message::header::ContentType;
+
+use bof_test::commands::attachment::EmailAttachment;
+use bof_test::commands::email_test::test_email_config;
+use bof_test::commands::outbox::{get_outbox_dir, queued_id};
+use bof_test::commands::send_email::{parse_recipients, send_email};
+use bof_test::commands::template::RenderedEmail;
+use bof_test::data_struct::{EmailConfig, EmailProfiles, SmtpSecurity};
+
+const USER: &str = "bof@example.com";
+const PASSWORD: &str = "secret";
+
+/// One message accepted by the server.
+#[derive(Debug, Clone)]
+struct Received {
+    from: String,
+    recipients: Vec<String>,
+    data: String,
+}
+
+struct Server {
+    port: u16,
+    received: Mutex<Vec<Received>>,
+}
+
+fn reply(stream: &mut TcpStream, line: &str) -> io::Result<()> {
+    stream.write_all(format!("{}\r\n", line).as_bytes())
+}
+
+fn angle_address(line: &str) -> String {
+    line.split_once('<')
+        .and_then(|(_, rest)| rest.split_once('>'))
+        .map(|(address, _)| address.to_string())
+        .unwrap_or_default()
+}
+
+fn valid_plain_auth(encoded: &str) -> bool {
+    let decoded = BASE64.decode(encoded.trim()).unwrap_or_default();
+    decoded == format!("\0{}\0{}", USER, PASSWORD).into_bytes()
+}
+
+fn serve(mut stream: TcpStream, server: &Server) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    reply(&mut stream, "220 localhost ESMTP test")?;
+
+    let mut from = String::new();
+    let mut recipients = Vec::new();
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 {
+            return Ok(());
+        }
+        let line = line.trim_end();
+        let command = line.split(' ').next().unwrap_or_default().to_ascii_uppercase();
+        match command.as_str() {
+            "EHLO" | "HELO" => reply(&mut stream, "250-localhost\r\n250 AUTH PLAIN LOGIN")?,
+            "AUTH" => {
+                let mut parts = line.split(' ').skip(1);
+                if parts.next().map(|m| m.to_ascii_uppercase()).as_deref() != Some("PLAIN") {
+                    reply(&mut stream, "504 5.5.4 only PLAIN")?;
+                    continue;
+                }
+                let encoded = match parts.next() {
+                    Some(encoded) => encoded.to_string(),
+                    None => {
+                        reply(&mut stream, "334 ")?;
+                        let mut response = String::new();
+                        reader.read_line(&mut response)?;
+                        response
+                    }
+                };
+                if valid_plain_auth(&encoded) {
+                    reply(&mut stream, "235 2.7.0 authenticated")?;
+                } else {
+                    reply(&mut stream, "535 5.7.8 bad credentials")?;
+                }
+            }
+            "MAIL" => {
+                from = angle_address(line);
+                recipients.clear();
+                reply(&mut stream, "250 2.1.0 ok")?;
+            }
+            "RCPT" => {
+                recipients.push(angle_address(line));
+                reply(&mut stream, "250 2.1.5 ok")?;
+            }
+            "DATA" => {
+                reply(&mut stream, "354 end with <CRLF>.<CRLF>")?;
+                let mut data = String::new();
+                loop {
+                    let mut line = String::new();
+                    if reader.read_line(&mut line)? == 0 || line == ".\r\n" {
+                        break;
+                    }
+                    data.push_str(&line);
+                }
+                server.received.lock().unwrap().push(Received {
+                    from: from.clone(),
+                    recipients: recipients.clone(),
+                    data,
+                });
+                reply(&mut stream, "250 2.0.0 queued")?;
+            }
+            "RSET" | "NOOP" => reply(&mut stream, "250 2.0.0 ok")?,
+            "QUIT" => return reply(&mut stream, "221 2.0.0 bye"),
+            _ => reply(&mut stream, "502 5.5.2 unknown command")?,
+        }
+    }
+}
+
+fn config(port: u16, password: &str) -> EmailConfig {
+    EmailConfig {
+        address: USER.to_string(),
+        password: password.to_string(),
+        encrypted_password: None,
+        password_env: None,
+        password_command: None,
+        server: "127.0.0.1".to_string(),
+        port,
+        security: SmtpSecurity::Plain,
+    }
+}
+
+/// The stored form of `config`: the password is read from `var` when sending.
+fn stored(port: u16, var: &str) -> EmailConfig {
+    EmailConfig { password: String::new(), password_env: Some(var.to_string()), ..config(port, "") }
+}
+
+/// Start the server once, with HOME pointing at a scratch directory holding
+/// a `good` and a `wrong` profile for it.
+fn server() -> &'static Server {
+    static SERVER: OnceLock<&'static Server> = OnceLock::new();
+    SERVER.get_or_init(|| {
+        let home = env::temp_dir().join(format!("bof-email-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&home);
+        fs::create_dir_all(home.join("bof_global")).unwrap();
+        env::set_var("HOME", &home);
+        env::set_var("BOF_TEST_SMTP_GOOD", PASSWORD);
+        env::set_var("BOF_TEST_SMTP_WRONG", "guess");
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server: &'static Server = Box::leak(Box::new(Server {
+            port: listener.local_addr().unwrap().port(),
+            received: Mutex::new(Vec::new()),
+        }));
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                thread::spawn(move || serve(stream, server));
+            }
+        });
+
+        let profiles = EmailProfiles {
+            default: Some("good".to_string()),
+            profiles: BTreeMap::from([
+                ("good".to_string(), stored(server.port, "BOF_TEST_SMTP_GOOD")),
+                ("wrong".to_string(), stored(server.port, "BOF_TEST_SMTP_WRONG")),
+            ]),
+        };
+        fs::write(home.join("bof_global/email_config.json"), serde_json::to_string(&profiles).unwrap()).unwrap();
+        server
+    })
+}
+
+fn received_for(server: &Server, recipient: &str) -> Vec<Received> {
+    let received = server.received.lock().unwrap();
+    received.iter().filter(|r| r.recipients.iter().any(|a| a == recipient)).cloned().collect()
+}
+
+#[test]
+fn send_email_delivers_message_and_attachments() {
+    let server = server();
+    let recipients = parse_recipients(
+        &["to@example.com".to_string()],
+        &["cc@example.com".to_string()],
+        &["hidden@example.com".to_string()],
+    )
+    .unwrap();
+    let email = RenderedEmail {
+        subject: "Metadata for report.pdf".to_string(),
+        text: "report.pdf is 3 bytes".to_string(),
+        html: None,
+    };
+    let attachments = [EmailAttachment {
+        file_name: "report.pdf".to_string(),
+        content_type: ContentType::parse("application/pdf").unwrap(),
+        data: b"pdf".to_vec(),
+    }];
+
+    send_email(&config(server.port, PASSWORD), None, &recipients, &email, &attachments).unwrap();
+
+    let received = received_for(server, "to@example.com");
+    assert_eq!(received.len(), 1);
+    let message = &received[0];
+    assert_eq!(message.from, USER);
+    assert_eq!(message.recipients, ["to@example.com", "cc@example.com", "hidden@example.com"]);
+    assert!(message.data.contains("Subject: Metadata for report.pdf"));
+    assert!(message.data.contains("report.pdf is 3 bytes"));
+    assert!(message.data.contains("filename=\"report.pdf\""));
+    assert!(message.data.contains("Content-Type: application/pdf\r\nContent-Transfer-Encoding: 7bit\r\n\r\npdf\r\n"));
+    assert!(!message.data.contains("hidden@example.com"), "Bcc stays out of the headers");
+}
+
+#[test]
+fn send_email_queues_a_message_the_server_refuses() {
+    let server = server();
+    let recipients = parse_recipients(&["refused@example.com".to_string()], &[], &[]).unwrap();
+    let email = RenderedEmail { subject: "Refused".to_string(), text: "body".to_string(), html: None };
+
+    let err = send_email(&config(server.port, "guess"), Some("wrong"), &recipients, &email, &[]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    let id = queued_id(&err).expect("the failed message is queued");
+    assert!(get_outbox_dir().unwrap().join(format!("{}.json", id)).exists());
+    assert!(received_for(server, "refused@example.com").is_empty());
+}
+
+#[test]
+fn test_email_config_authenticates_and_sends() {
+    let server = server();
+    test_email_config(None, Some("probe@example.com")).unwrap();
+
+    let received = received_for(server, "probe@example.com");
+    assert_eq!(received.len(), 1);
+    assert!(received[0].data.contains("Subject: bof test message"));
+}
+
+#[test]
+fn test_email_config_reports_the_failing_stage() {
+    server();
+    let err = test_email_config(Some("wrong"), None).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::PermissionDenied);
+    assert!(err.to_string().starts_with("auth stage failed"), "{}", err);
+
+    assert_eq!(test_email_config(Some("missing"), None).unwrap_err().kind(), ErrorKind::NotFound);
+    assert_eq!(test_email_config(None, Some("not an address")).unwrap_err().kind(), ErrorKind::InvalidInput);
+}
@@ -0,0 +1,102 @@
+//! `post_json` against an in-process HTTP server that, like many real ones,
+//! keeps the connection open after answering.
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use bof_test::commands::webhook::{post_json, sign};
+
+/// The request line, headers and body the server received.
+struct Request {
+    line: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+/// Answer one request with `response`, then hold the socket open until the
+/// returned sender is dropped. Returns the server's URL and what it received.
+fn serve_once(response: &'static str) -> (String, mpsc::Receiver<Request>, mpsc::Sender<()>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
+    let (request_tx, request_rx) = mpsc::channel();
+    let (done_tx, done_rx) = mpsc::channel::<()>();
+
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+
+        let mut headers = Vec::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            match header.trim_end().split_once(": ") {
+                Some((name, value)) => headers.push((name.to_string(), value.to_string())),
+                None => break,
+            }
+        }
+        let length = headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, v)| v.parse().ok())
+            .unwrap_or(0);
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+
+        stream.write_all(response.as_bytes()).unwrap();
+        request_tx.send(Request { line: line.trim_end().to_string(), headers, body }).unwrap();
+        let _ = done_rx.recv();
+    });
+    (url, request_rx, done_tx)
+}
+
+#[test]
+fn post_json_sends_the_request_and_returns_without_waiting_for_close() {
+    let (url, requests, _done) = serve_once("HTTP/1.1 202 Accepted\r\nContent-Length: 2\r\nConnection: keep-alive\r\n\r\nok");
+    let body = br#"{"event":"file.metadata"}"#;
+    let signature = sign(b"topsecret", body);
+
+    let started = Instant::now();
+    let status = post_json(
+        &format!("{}/hooks/bof?team=ops#ignored", url.replace("http://", "http://bob:p%40ss@")),
+        body,
+        &[("X-Bof-Signature", signature.clone())],
+    )
+    .unwrap();
+    assert_eq!(status, 202);
+    assert!(started.elapsed() < Duration::from_secs(5), "post_json waited for the server to close");
+
+    let request = requests.recv().unwrap();
+    assert_eq!(request.line, "POST /hooks/bof?team=ops HTTP/1.1");
+    assert_eq!(request.header("Content-Type"), Some("application/json"));
+    assert_eq!(request.header("Authorization"), Some("Basic Ym9iOnBAc3M="));
+    assert_eq!(request.header("X-Bof-Signature"), Some(signature.as_str()));
+    assert_eq!(request.body, body);
+}
+
+#[test]
+fn post_json_reports_error_statuses_with_the_response_body() {
+    let (url, _requests, _done) =
+        serve_once("HTTP/1.1 403 Forbidden\r\nContent-Length: 17\r\n\r\nbad signature\r\nxx");
+
+    let err = post_json(&format!("{}/in", url.replace("http://", "http://bob:hunter2@")), b"{}", &[]).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("answered HTTP 403 bad signature"), "{}", message);
+    assert!(!message.contains("hunter2"), "the password is not shown: {}", message);
+}
+
+#[test]
+fn post_json_fails_when_nothing_listens() {
+    let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    assert!(post_json(&format!("http://127.0.0.1:{}/", port), b"{}", &[]).is_err());
+}